@@ -0,0 +1,385 @@
+use crate::encoding;
+use crate::opcodes::OpCode;
+
+/// Errors produced while assembling. Every variant carries the 1-based source line it occurred on.
+#[derive(Debug, Display, PartialEq)]
+pub enum AsmError {
+    #[display("Line {}: Unknown mnemonic {}", _0, _1)]
+    UnknownMnemonic(usize, String),
+    #[display("Line {}: Expected {} operands, found {}", _0, _1, _2)]
+    OperandCount(usize, usize, usize),
+    #[display("Line {}: Invalid register {}", _0, _1)]
+    InvalidRegister(usize, String),
+    #[display("Line {}: Invalid value {}", _0, _1)]
+    InvalidValue(usize, String),
+    #[display("Line {}: Value {} doesn't fit into {} bits", _0, _1, _2)]
+    ValueOutOfRange(usize, i64, u32),
+    #[display("Line {}: Undefined label {}", _0, _1)]
+    UndefinedLabel(usize, String),
+    #[display("Line {}: Label {} is defined more than once", _0, _1)]
+    DuplicateLabel(usize, String),
+    #[display("Line {}: .org 0x{:X} is behind the current address 0x{:X}", _0, _1, _2)]
+    BackwardOrg(usize, u32, u32),
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles a program into a memory image that is loaded starting at address 0.
+///
+/// The syntax is line based. Everything after `;` is a comment, `name:` defines a label at the
+/// current address and every other line is either an instruction or a directive:
+///
+/// - `mnemonic op, op, ...`: Any `OpCode` name in any case, e.g. `load_imm r3, 1`. Registers are
+///   written as `r0` - `r31`, values in decimal, `0x` hex or `0b` binary. Immediate and address
+///   operands also accept labels, relative jumps and branches are resolved to an offset from the
+///   following instruction. `load_full r1, value` emits its 32-bit constant as a second word.
+///   `jump label` is short for `jump_imm label`.
+/// - `.word value`: Emits a raw little-endian 32-bit word, for example a reset vector.
+/// - `.org address`: Continues assembling at `address`, filling the gap with zeros. The address
+///   can't be lower than the current one.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let lines: Vec<(usize, &str)> = source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split(';').next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+
+    // First pass: assign an address to every label.
+    let mut labels = std::collections::HashMap::new();
+    let mut address: u32 = 0;
+    for &(number, line) in &lines {
+        let line = match line.split_once(':') {
+            Some((label, rest)) => {
+                let label = label.trim();
+                if labels.insert(label.to_string(), address).is_some() {
+                    return Err(AsmError::DuplicateLabel(number, label.to_string()));
+                }
+                rest.trim()
+            }
+            None => line,
+        };
+        if line.is_empty() {
+            continue;
+        }
+        let (mnemonic, operands) = split_line(line);
+        if mnemonic == ".org" {
+            address = parse_value(number, operands.first().copied().unwrap_or(""), &labels)? as u32;
+        } else {
+            // Unknown mnemonics are reported by the second pass
+            address += parse_mnemonic(number, &mnemonic).map_or(4, |opcode| opcode.length());
+        }
+    }
+
+    // Second pass: encode.
+    let mut image: Vec<u8> = Vec::new();
+    for &(number, line) in &lines {
+        let line = match line.split_once(':') {
+            Some((_, rest)) => rest.trim(),
+            None => line,
+        };
+        if line.is_empty() {
+            continue;
+        }
+        let (mnemonic, operands) = split_line(line);
+        let words = match mnemonic.as_str() {
+            ".org" => {
+                expect_operands(number, &operands, 1)?;
+                let target = parse_value(number, operands[0], &labels)? as usize;
+                if target < image.len() {
+                    return Err(AsmError::BackwardOrg(number, target as u32, image.len() as u32));
+                }
+                image.resize(target, 0);
+                continue;
+            }
+            ".word" => {
+                expect_operands(number, &operands, 1)?;
+                vec![check_range(number, parse_value(number, operands[0], &labels)?, 32)? as u32]
+            }
+            _ => encode_instruction(number, &mnemonic, &operands, image.len() as u32 + 4, &labels)?,
+        };
+        for word in words {
+            image.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+    Ok(image)
+}
+
+fn split_line(line: &str) -> (String, Vec<&str>) {
+    let (mnemonic, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let operands = rest
+        .split(',')
+        .map(str::trim)
+        .filter(|operand| !operand.is_empty())
+        .collect();
+    (mnemonic.to_lowercase(), operands)
+}
+
+fn encode_instruction(
+    number: usize,
+    mnemonic: &str,
+    operands: &[&str],
+    next_address: u32,
+    labels: &std::collections::HashMap<String, u32>,
+) -> Result<Vec<u32>, AsmError> {
+    let opcode = parse_mnemonic(number, mnemonic)?;
+    let mut constant = None;
+    let register = |i: usize| parse_register(number, operands[i]);
+    let value = |i: usize| parse_value(number, operands[i], labels);
+
+    let instruction = match opcode {
+        OpCode::NOOP
+        | OpCode::RTRN
+        | OpCode::RTRN_POP
+        | OpCode::RSET_SOFT
+        | OpCode::RSET_HARD
+        | OpCode::CLI
+        | OpCode::SEI
+        | OpCode::CLC
+        | OpCode::SHUTDOWN
+        | OpCode::HALT => {
+            expect_operands(number, operands, 0)?;
+            encoding::encode(opcode, 0)
+        }
+        OpCode::LOAD_IMM
+        | OpCode::LDUP_IMM
+        | OpCode::STOR_IMM
+        | OpCode::ORI
+        | OpCode::ADDI
+        | OpCode::SUBI => {
+            expect_operands(number, operands, 2)?;
+            encoding::encode_i(opcode, register(0)?, check_range(number, value(1)?, 20)? as u32)
+        }
+        OpCode::JUMP_REG
+        | OpCode::BRAN_REG
+        | OpCode::PUSH
+        | OpCode::CLR
+        | OpCode::POP
+        | OpCode::COREID
+        | OpCode::RDSP
+        | OpCode::WRSP
+        | OpCode::RDPC => {
+            expect_operands(number, operands, 1)?;
+            encoding::encode_r(opcode, register(0)?, 0, 0)
+        }
+        OpCode::LOAD_FULL => {
+            expect_operands(number, operands, 2)?;
+            constant = Some(check_range(number, value(1)?, 32)? as u32);
+            encoding::encode_r(opcode, register(0)?, 0, 0)
+        }
+        OpCode::LOAD_BYTE
+        | OpCode::LOAD_WORD
+        | OpCode::LOAD_HALF
+        | OpCode::LOAD_HALF_S
+        | OpCode::STOR_BYTE
+        | OpCode::STOR_HALF
+        | OpCode::MOV
+        | OpCode::NOT
+        | OpCode::CMP
+        | OpCode::CMP_LT
+        | OpCode::CMP_LTU => {
+            expect_operands(number, operands, 2)?;
+            encoding::encode_r(opcode, register(0)?, register(1)?, 0)
+        }
+        OpCode::ADD
+        | OpCode::SUB
+        | OpCode::ADC
+        | OpCode::SBC
+        | OpCode::MUL
+        | OpCode::DIV
+        | OpCode::MOD
+        | OpCode::AND
+        | OpCode::ORR
+        | OpCode::XOR
+        | OpCode::NAND
+        | OpCode::NOR
+        | OpCode::SHL
+        | OpCode::SHR
+        | OpCode::SAR
+        | OpCode::CAS
+        | OpCode::JUEQ_REG
+        | OpCode::BREQ_REG => {
+            expect_operands(number, operands, 3)?;
+            encoding::encode_r(opcode, register(0)?, register(1)?, register(2)?)
+        }
+        OpCode::JUMP_IMM
+        | OpCode::BRAN_IMM
+        | OpCode::BEQ_IMM
+        | OpCode::BNE_IMM
+        | OpCode::BLT_IMM
+        | OpCode::BGE_IMM
+        | OpCode::YIELD => {
+            expect_operands(number, operands, 1)?;
+            encoding::encode_j(opcode, check_range(number, value(0)?, 25)? as u32)
+        }
+        OpCode::JUMP_REL | OpCode::CALL_REL => {
+            expect_operands(number, operands, 1)?;
+            let offset = relative_offset(number, operands[0], next_address, labels)?;
+            encoding::encode_j(opcode, check_signed_range(number, offset, 25)? as u32)
+        }
+        OpCode::IRPT_SEND => {
+            let vector = if operands.len() == 2 {
+                0
+            } else {
+                expect_operands(number, operands, 3)?;
+                check_range(number, value(2)?, 5)? as u32
+            };
+            encoding::encode_r(
+                opcode,
+                check_range(number, value(0)?, 5)? as u32,
+                check_range(number, value(1)?, 5)? as u32,
+                vector,
+            )
+        }
+        OpCode::BTST => {
+            expect_operands(number, operands, 2)?;
+            encoding::encode_r(opcode, register(0)?, check_range(number, value(1)?, 5)? as u32, 0)
+        }
+        OpCode::IRPT_SEND_DATA => {
+            expect_operands(number, operands, 2)?;
+            encoding::encode_r(opcode, check_range(number, value(0)?, 5)? as u32, register(1)?, 0)
+        }
+    };
+    Ok(std::iter::once(instruction).chain(constant).collect())
+}
+
+fn parse_mnemonic(number: usize, mnemonic: &str) -> Result<OpCode, AsmError> {
+    if mnemonic.eq_ignore_ascii_case("jump") {
+        return Ok(OpCode::JUMP_IMM);
+    }
+    (0..0x80u32)
+        .filter_map(|value| OpCode::try_from(value).ok())
+        .find(|opcode| format!("{}", opcode).eq_ignore_ascii_case(mnemonic))
+        .ok_or_else(|| AsmError::UnknownMnemonic(number, mnemonic.to_string()))
+}
+
+/// Labels resolve to their distance from `next_address`, plain numbers are taken as offsets.
+fn relative_offset(
+    number: usize,
+    operand: &str,
+    next_address: u32,
+    labels: &std::collections::HashMap<String, u32>,
+) -> Result<i64, AsmError> {
+    match labels.get(operand) {
+        Some(&target) => Ok(target as i64 - next_address as i64),
+        None => parse_value(number, operand, labels),
+    }
+}
+
+fn expect_operands(number: usize, operands: &[&str], count: usize) -> Result<(), AsmError> {
+    if operands.len() != count {
+        return Err(AsmError::OperandCount(number, count, operands.len()));
+    }
+    Ok(())
+}
+
+fn parse_register(number: usize, operand: &str) -> Result<u32, AsmError> {
+    operand
+        .strip_prefix(['r', 'R'])
+        .and_then(|index| index.parse::<u32>().ok())
+        .filter(|&index| index < 32)
+        .ok_or_else(|| AsmError::InvalidRegister(number, operand.to_string()))
+}
+
+fn parse_value(
+    number: usize,
+    operand: &str,
+    labels: &std::collections::HashMap<String, u32>,
+) -> Result<i64, AsmError> {
+    if let Some(&address) = labels.get(operand) {
+        return Ok(address as i64);
+    }
+    let (negative, digits) = match operand.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, operand),
+    };
+    let parsed = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2)
+    } else {
+        digits.parse::<i64>()
+    };
+    match parsed {
+        Ok(value) if negative => Ok(-value),
+        Ok(value) => Ok(value),
+        Err(_) if digits.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => {
+            Err(AsmError::UndefinedLabel(number, operand.to_string()))
+        }
+        Err(_) => Err(AsmError::InvalidValue(number, operand.to_string())),
+    }
+}
+
+fn check_range(number: usize, value: i64, bits: u32) -> Result<i64, AsmError> {
+    if value < 0 || value >= 1 << bits {
+        return Err(AsmError::ValueOutOfRange(number, value, bits));
+    }
+    Ok(value)
+}
+
+fn check_signed_range(number: usize, value: i64, bits: u32) -> Result<i64, AsmError> {
+    if value < -(1 << (bits - 1)) || value >= 1 << (bits - 1) {
+        return Err(AsmError::ValueOutOfRange(number, value, bits));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_labels_and_directives() {
+        let source = "
+            .word start          ; core 0 reset vector
+            .org 0x10
+        start:
+            load_imm r3, 0x1
+        loop:
+            ADD r1, r2, r3
+            jump_rel loop
+        ";
+        let image = assemble(source).unwrap();
+        let word = |addr: usize| u32::from_le_bytes(image[addr..addr + 4].try_into().unwrap());
+        assert_eq!(image.len(), 0x1C);
+        assert_eq!(word(0x0), 0x10);
+        assert_eq!(crate::disasm::disassemble(word(0x10)), "LOAD_IMM r3, 0x1");
+        assert_eq!(crate::disasm::disassemble(word(0x14)), "ADD r1, r2, r3");
+        assert_eq!(crate::disasm::disassemble(word(0x18)), "JUMP_REL -0x8");
+
+        let image = assemble("load_full r1, 0xDEADBEEF\nend: jump_imm end").unwrap();
+        let word = |addr: usize| u32::from_le_bytes(image[addr..addr + 4].try_into().unwrap());
+        assert_eq!(image.len(), 12);
+        assert_eq!(crate::disasm::disassemble(word(0x0)), "LOAD_FULL r1");
+        assert_eq!(word(0x4), 0xDEADBEEF);
+        assert_eq!(crate::disasm::disassemble(word(0x8)), "JUMP_IMM 0x00000008");
+
+        assert_eq!(assemble("end: jump end").unwrap(), assemble("end: jump_imm end").unwrap());
+        assert_eq!(assemble("jmp nowhere"), Err(AsmError::UnknownMnemonic(1, "jmp".to_string())));
+        assert_eq!(assemble("jump_imm nowhere"), Err(AsmError::UndefinedLabel(1, "nowhere".to_string())));
+        assert_eq!(assemble(".org 0x10\nnoop\n.org 0x8"), Err(AsmError::BackwardOrg(3, 0x8, 0x14)));
+        assert_eq!(assemble("mov r1, r32"), Err(AsmError::InvalidRegister(1, "r32".to_string())));
+    }
+
+    #[test]
+    fn assembled_programs_run_on_a_core() {
+        let source = "
+            .word start
+            .org 0x10
+        start:
+            load_imm r1, 3
+            load_imm r2, 4
+            call_rel square
+            halt
+        square:
+            mul r3, r1, r2
+            rtrn_pop
+        ";
+        let mut core = crate::core::Core::new_for_test(&assemble(source).unwrap());
+        for _ in 0..5 {
+            core.tick().unwrap();
+        }
+        assert_eq!(core.registers[3], 12);
+        assert_eq!(core.program_counter, 0x1C);
+    }
+}
@@ -0,0 +1,146 @@
+use std::io::{Read, Seek, Write};
+
+/// Size of one block in bytes.
+pub const SECTOR_SIZE: usize = 512;
+
+/// Memory mapped block storage backed by a host file. Transfers whole sectors between the file
+/// and a buffer in RAM.
+///
+/// Like the timer, every byte offset of the device maps to one whole register:
+///
+/// - `0`: Sector number.
+/// - `1`: RAM address of the `SECTOR_SIZE` byte DMA buffer.
+/// - `2`: Command. Writing 1 reads the sector into the buffer, writing 2 writes the buffer to the
+///   sector. Reads 0.
+/// - `3`: Status of the last command (read-only). 0 on success, 1 if it failed.
+///
+/// Sectors past the end of the file read as zeroes, writing them grows the file.
+pub struct BlockDevice {
+    pub registers: [u32; 4],
+    pub file: std::fs::File,
+    pub ram: std::sync::Arc<std::sync::RwLock<crate::memory::Memory>>,
+}
+
+impl BlockDevice {
+    pub fn new(file: std::fs::File, ram: std::sync::Arc<std::sync::RwLock<crate::memory::Memory>>) -> Self {
+        info!("Created BlockDevice");
+        Self {
+            registers: [0; 4],
+            file,
+            ram,
+        }
+    }
+
+    fn execute(&mut self, command: u32) -> std::io::Result<()> {
+        let offset = self.registers[0] as u64 * SECTOR_SIZE as u64;
+        let start = self.registers[1] as usize;
+        let mut ram = self.ram.write().unwrap();
+        let buffer = ram.data.get_mut(start..start + SECTOR_SIZE).ok_or_else(|| {
+            std::io::Error::other(format!("DMA buffer at 0x{:08X} exceeds the address space", start))
+        })?;
+        match command {
+            1 => {
+                // Short reads at the end of the file leave the rest of the sector zeroed
+                buffer.fill(0);
+                self.file.seek(std::io::SeekFrom::Start(offset))?;
+                let mut read = 0;
+                while read < SECTOR_SIZE {
+                    match self.file.read(&mut buffer[read..])? {
+                        0 => break,
+                        n => read += n,
+                    }
+                }
+                Ok(())
+            }
+            2 => {
+                self.file.seek(std::io::SeekFrom::Start(offset))?;
+                self.file.write_all(buffer)
+            }
+            _ => Err(std::io::Error::other(format!("Unknown command {}", command))),
+        }
+    }
+}
+
+impl crate::mmio::AddressSpace for BlockDevice {
+    fn read8(&self, addr_offset: u32) -> u8 {
+        self.read32(addr_offset) as u8
+    }
+    fn read32(&self, addr_offset: u32) -> u32 {
+        match addr_offset {
+            0 | 1 | 3 => self.registers[addr_offset as usize],
+            2 => 0,
+            _ => {
+                error!("Address offset out of bounds!");
+                0
+            }
+        }
+    }
+    fn write8(&mut self, addr_offset: u32, value: u8) {
+        self.write32(addr_offset, value as u32);
+    }
+    fn write32(&mut self, addr_offset: u32, value: u32) {
+        match addr_offset {
+            0 | 1 => {
+                self.registers[addr_offset as usize] = value;
+                info!("BlockDevice received value {} at offset {}", value, addr_offset);
+            }
+            2 => {
+                info!("BlockDevice executing command {} on sector {}", value, self.registers[0]);
+                self.registers[3] = match self.execute(value) {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        error!("BlockDevice command {} failed: {}", value, e);
+                        1
+                    }
+                };
+            }
+            3 => error!("BlockDevice status is read-only, ignoring write of {}", value),
+            _ => error!("Address offset out of bounds!"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::AddressSpace;
+
+    #[test]
+    fn transfers_sectors_and_zero_fills_past_the_end() {
+        let path = std::env::temp_dir().join(format!("rusty-vm-block-{}.img", std::process::id()));
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        let ram = std::sync::Arc::new(std::sync::RwLock::new(crate::memory::Memory::empty(0x1000)));
+        let mut disk = BlockDevice::new(file, ram.clone());
+
+        ram.write().unwrap().data[0x200..0x400].fill(0xAB);
+        disk.write32(0, 1);
+        disk.write32(1, 0x200);
+        disk.write32(2, 2);
+        assert_eq!(disk.read32(3), 0);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 2 * SECTOR_SIZE as u64);
+
+        disk.write32(1, 0x600);
+        disk.write32(2, 1);
+        assert_eq!(&ram.read().unwrap().data[0x600..0x800], &[0xAB; SECTOR_SIZE]);
+
+        ram.write().unwrap().data[0x600..0x800].fill(0xFF);
+        disk.write32(0, 5);
+        disk.write32(2, 1);
+        assert_eq!(disk.read32(3), 0);
+        assert_eq!(&ram.read().unwrap().data[0x600..0x800], &[0; SECTOR_SIZE]);
+
+        // A sector cut short by the end of the file
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(&[0xCD; 0x10]).unwrap();
+        disk.write32(0, 2);
+        disk.write32(2, 1);
+        assert_eq!(disk.read32(3), 0);
+        assert_eq!(&ram.read().unwrap().data[0x600..0x610], &[0xCD; 0x10]);
+        assert_eq!(&ram.read().unwrap().data[0x610..0x800], &[0; SECTOR_SIZE - 0x10]);
+
+        disk.write32(1, 0xF00);
+        disk.write32(2, 1);
+        assert_eq!(disk.read32(3), 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
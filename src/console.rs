@@ -0,0 +1,49 @@
+/// Memory mapped serial console for text output without the GPU.
+///
+/// # ==== Registers ====
+///
+/// - `0x0`: Data. Writing a byte sends it to the console output, reads return 0.
+/// - `0x1`: Status. Bit 0 is set while the console can accept a byte, which is always the case.
+///
+/// Word writes to the data register send their lowest byte.
+pub struct Console {
+    pub writer: Box<dyn std::io::Write + Send>,
+}
+
+impl Console {
+    pub fn new(writer: Box<dyn std::io::Write + Send>) -> Self {
+        info!("Created Console");
+        Self { writer }
+    }
+}
+
+impl crate::mmio::AddressSpace for Console {
+    fn read8(&self, addr_offset: u32) -> u8 {
+        match addr_offset {
+            0x0 => 0,
+            0x1 => 0b1,
+            _ => {
+                error!("Address offset out of bounds!");
+                0
+            }
+        }
+    }
+    fn read32(&self, addr_offset: u32) -> u32 {
+        self.read8(addr_offset) as u32
+    }
+    fn write8(&mut self, addr_offset: u32, value: u8) {
+        match addr_offset {
+            0x0 => {
+                // Flushed right away so partial lines show up while the guest is still running
+                if let Err(e) = self.writer.write_all(&[value]).and_then(|_| self.writer.flush()) {
+                    error!("Could not write to console: {}", e);
+                }
+            }
+            0x1 => error!("Console status register is read-only, ignoring write of {}", value),
+            _ => error!("Address offset out of bounds!"),
+        }
+    }
+    fn write32(&mut self, addr_offset: u32, value: u32) {
+        self.write8(addr_offset, value as u8);
+    }
+}
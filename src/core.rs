@@ -1,59 +1,210 @@
 use crate::OpCode;
-use crate::cpu::{CpuError, CpuErrorType, Interrupt, InterruptType};
+use crate::encoding;
+use crate::cpu::{ArithMode, CpuError, CpuErrorType, Interrupt, InterruptType};
 use crate::mmio::AddressSpace;
 
+/// Address of the reset vector table, see `Core`.
+pub const RESET_VECTOR_TABLE: u32 = 0x0;
+/// Highest number of cores, limited by the 5 bit target field of `IRPT_SEND`.
+pub const MAX_CORES: u32 = 32;
+
+/// Address of the interrupt vector table of a VM with `cores` cores. It directly follows the reset
+/// vector table, but never starts below 0x10 so programs written for up to 4 cores keep working.
+pub fn interrupt_vector_table(cores: u32) -> u32 {
+    (RESET_VECTOR_TABLE + cores * 4).max(0x10)
+}
+
+/// Default stack region of a VM with `memory_size` bytes of memory as `(base, size)`: the second
+/// quarter of the memory, which is 0x4000_0000 - 0x8000_0000 in the full 32-bit address space.
+pub fn default_stack(memory_size: usize) -> (u32, u32) {
+    let quarter = (memory_size / 4) as u32;
+    (quarter, quarter)
+}
+
+/// A single core of the CPU.
+///
+/// # ==== Execution state ====
+///
+/// - `busy`: The core has work and is executing instructions. Only core 0 starts busy, all other
+///   cores idle until they are resumed.
+/// - `halted`: The core has been explicitly stopped by a Halt interrupt and does not execute
+///   anything until it receives a Resume interrupt.
+///
+/// A core only ticks while it is busy and not halted. A Resume interrupt clears `halted`; if the
+/// core was idle it additionally starts executing from its reset vector and becomes busy. Idle and
+/// halted cores block on their interrupt receiver instead of spinning.
+///
+/// # ==== Reset vector table ====
+///
+/// The table at `RESET_VECTOR_TABLE` holds one entry per core: the start address of core `n` is
+/// the little-endian u32 at `RESET_VECTOR_TABLE + n * 4`. The interrupt vector table follows it,
+/// see `interrupt_vector_table`. A core jumps to it when it is created, resumed while idle or
+/// reset. Entries pointing outside of the memory fail with `InvalidResetVector`.
 pub struct Core {
     pub program_counter: u32,
     pub stack_pointer: u32,
+    /// Lowest address of the stack region, where the stack pointer starts.
+    pub stack_base: u32,
+    /// Size of the stack region in bytes. The stack grows upwards from `stack_base`.
+    pub stack_size: u32,
     pub registers: [u32; 32],
     pub eq_flag: bool,
+    pub lt_flag: bool,
+    /// Set by an addition that carries out of bit 31 or a subtraction that borrows.
+    pub carry_flag: bool,
+    /// Set when the result of an addition or subtraction overflows as a signed value.
+    pub overflow_flag: bool,
     pub index: u32,
     pub busy: bool,
     pub halted: bool,
+    /// Whether hardware IRQs are delivered. While cleared, incoming IRQs are queued in
+    /// `pending_irqs` until `SEI` enables them again.
+    pub interrupts_enabled: bool,
+    /// IRQ vectors waiting for their handler, oldest first. A vector is pending at most once, IRQs
+    /// arriving while it is already pending are merged into it.
+    pub pending_irqs: std::collections::VecDeque<u32>,
+    /// Address of the interrupt vector table, after the reset vectors of all cores.
+    pub interrupt_vector_table: u32,
     pub receiver: std::sync::mpsc::Receiver<Interrupt>,
-    pub senders: [std::sync::mpsc::Sender<Interrupt>; 4],
+    pub senders: Vec<std::sync::mpsc::Sender<Interrupt>>,
     pub bus: std::sync::Arc<std::sync::RwLock<crate::mmio::Bus>>,
-    pub running: std::sync::Arc<std::sync::atomic::AtomicBool>
+    pub running: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    /// Time a single instruction takes. `None` runs the core as fast as possible.
+    pub cycle_duration: Option<std::time::Duration>,
+    /// Address of the instruction currently executing, or of the next instruction while
+    /// interrupts are being handled. Errors report it so the faulting instruction can be retried.
+    pub instruction_address: u32,
+    /// Number of instructions this core has successfully executed.
+    pub instructions_retired: u64,
+    /// Instructions executed by all cores together, shared between them.
+    pub total_retired: std::sync::Arc<std::sync::atomic::AtomicU64>,
+    /// Stop the VM once `total_retired` reaches this many instructions.
+    pub max_cycles: Option<u64>,
+    /// Size of the RAM behind `bus`. Accesses beyond it fail instead of panicking.
+    pub memory_size: usize,
+    /// Whether `ADD`, `SUB`, `ADDI` and `SUBI` report overflows or silently wrap around.
+    pub arith: ArithMode,
+    /// Print every instruction with the register file and wait for ENTER before executing it.
+    pub step: bool
 }
 
 impl Core {
     pub fn new(
         index: u32,
-        senders: [std::sync::mpsc::Sender<Interrupt>; 4],
+        senders: Vec<std::sync::mpsc::Sender<Interrupt>>,
         receiver: std::sync::mpsc::Receiver<Interrupt>,
         memory: std::sync::Arc<std::sync::RwLock<crate::mmio::Bus>>,
-        running: std::sync::Arc<std::sync::atomic::AtomicBool>
+        running: std::sync::Arc<std::sync::atomic::AtomicBool>,
+        clock_hz: u32
     ) -> Self {
         info!("Created Core with index {index}");
+        let memory_size = memory.read().unwrap().ram.read().unwrap().data.len();
+        let (stack_base, stack_size) = default_stack(memory_size);
         let mut core = Self {
             program_counter: 0x0000_0000 + index * 4,
-            stack_pointer: 0x4000_0000,
+            stack_pointer: stack_base,
+            stack_base,
+            stack_size,
             registers: [0; 32],
             eq_flag: false,
+            lt_flag: false,
+            carry_flag: false,
+            overflow_flag: false,
             index: index,
             busy: false,
             halted: false,
+            interrupts_enabled: true,
+            pending_irqs: std::collections::VecDeque::new(),
+            interrupt_vector_table: interrupt_vector_table(senders.len() as u32),
             senders,
             receiver,
             bus: memory,
-            running
+            running,
+            cycle_duration: match clock_hz {
+                0 => None,
+                hz => Some(std::time::Duration::from_secs(1) / hz),
+            },
+            instruction_address: 0,
+            instructions_retired: 0,
+            total_retired: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            max_cycles: None,
+            memory_size,
+            arith: ArithMode::Checked,
+            step: false
         };
-        core.reset_hard();
+        if let Err(e) = core.reset_hard() {
+            warn!("Core {} has no valid reset vector: {}", index, e);
+        }
         return core;
     }
 
-    fn reset_soft(&mut self) {
-        self.program_counter = 0x0 + self.index * 4;
-        let new_addr = self.fetch_u32();
+    /// Number of instructions this core has successfully executed since it was created.
+    pub fn instructions_retired(&self) -> u64 {
+        self.instructions_retired
+    }
+
+    pub fn state(&self) -> crate::snapshot::CoreState {
+        crate::snapshot::CoreState {
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            registers: self.registers,
+            eq_flag: self.eq_flag,
+            lt_flag: self.lt_flag,
+            carry_flag: self.carry_flag,
+            overflow_flag: self.overflow_flag,
+            busy: self.busy,
+            halted: self.halted,
+            interrupts_enabled: self.interrupts_enabled,
+            instructions_retired: self.instructions_retired,
+        }
+    }
+
+    /// Overwrites the architectural state with `state`. Queued IRQs are dropped.
+    pub fn restore_state(&mut self, state: &crate::snapshot::CoreState) {
+        self.program_counter = state.program_counter;
+        self.stack_pointer = state.stack_pointer;
+        self.registers = state.registers;
+        self.eq_flag = state.eq_flag;
+        self.lt_flag = state.lt_flag;
+        self.carry_flag = state.carry_flag;
+        self.overflow_flag = state.overflow_flag;
+        self.busy = state.busy;
+        self.halted = state.halted;
+        self.interrupts_enabled = state.interrupts_enabled;
+        self.instructions_retired = state.instructions_retired;
+        self.pending_irqs.clear();
+    }
+
+    /// Jumps to the entry of this core in the reset vector table and resets the stack pointer.
+    fn reset_soft(&mut self) -> Result<(), CpuError> {
+        let new_addr = self.read_word(RESET_VECTOR_TABLE + self.index * 4)?;
+        if new_addr as usize + 4 > self.memory_size {
+            return Err(CpuError::new(
+                self.program_counter,
+                self.instruction_address,
+                self.stack_pointer,
+                self.registers,
+                CpuErrorType::InvalidResetVector(new_addr),
+                self.index,
+                self.instructions_retired,
+            ));
+        }
         self.program_counter = new_addr;
-        self.stack_pointer = 0x4000_0000;
+        self.stack_pointer = self.stack_base;
+        Ok(())
     }
 
-    fn reset_hard(&mut self) {
-        self.reset_soft();
+    fn reset_hard(&mut self) -> Result<(), CpuError> {
+        self.reset_soft()?;
         for register in self.registers.iter_mut() {
             *register = 0;
         }
+        Ok(())
+    }
+
+    /// First address above the stack region.
+    fn stack_top(&self) -> u64 {
+        self.stack_base as u64 + self.stack_size as u64
     }
 
     /// Advances the program counter by one. Wrapping.
@@ -65,372 +216,1544 @@ impl Core {
         }
     }
 
-    /// Advances the stack pointer by one. Wrapping.
-    fn advance_sp(&mut self) {
-        if self.stack_pointer < 0x8000_0000 {
-            self.stack_pointer += 1;
-        } else {
-            self.stack_pointer = 0x4000_0000;
-        }
-    }
-
-    /// Moves the stack pointer back by one. Wrapping.
-    fn decrease_sp(&mut self) {
-        if self.stack_pointer > 0x4000_0000 {
-            self.stack_pointer -= 1;
-        } else {
-            self.stack_pointer = 0x7FFF_FFFF;
+    /// Fails with `MemoryFault` if the `len` bytes at `address` lie outside of the memory.
+    fn check_bounds(
+        &self,
+        address: u32,
+        len: u32,
+    ) -> Result<(), CpuError> {
+        if address as usize + len as usize > self.memory_size {
+            return Err(CpuError::new(
+                self.program_counter,
+                self.instruction_address,
+                self.stack_pointer,
+                self.registers,
+                CpuErrorType::MemoryFault(address),
+                self.index,
+                self.instructions_retired,
+            ));
         }
+        Ok(())
     }
 
     fn write_byte(
         &mut self,
         address: u32,
         value: u8,
-    ) {
+    ) -> Result<(), CpuError> {
+        self.check_bounds(address, 1)?;
         self.bus.write().unwrap().write8(address, value);
+        Ok(())
     }
 
     fn read_byte(
         &self,
         address: u32,
-    ) -> u8 {
-        self.bus.read().unwrap().read8(address)
+    ) -> Result<u8, CpuError> {
+        self.check_bounds(address, 1)?;
+        Ok(self.bus.read().unwrap().read8(address))
+    }
+
+    fn write_half(
+        &mut self,
+        address: u32,
+        value: u16,
+    ) -> Result<(), CpuError> {
+        self.check_bounds(address, 2)?;
+        let [low, high] = value.to_le_bytes();
+        let mut bus = self.bus.write().unwrap();
+        bus.write8(address, low);
+        bus.write8(address + 1, high);
+        Ok(())
+    }
+
+    fn read_half(
+        &self,
+        address: u32,
+    ) -> Result<u16, CpuError> {
+        self.check_bounds(address, 2)?;
+        let bus = self.bus.read().unwrap();
+        Ok(u16::from_le_bytes([bus.read8(address), bus.read8(address + 1)]))
+    }
+
+    fn write_word(
+        &mut self,
+        address: u32,
+        value: u32,
+    ) -> Result<(), CpuError> {
+        self.check_bounds(address, 4)?;
+        self.bus.write().unwrap().write32(address, value);
+        Ok(())
     }
 
+    fn read_word(
+        &self,
+        address: u32,
+    ) -> Result<u32, CpuError> {
+        self.check_bounds(address, 4)?;
+        Ok(self.bus.read().unwrap().read32(address))
+    }
+
+    /// Pushes a word onto the stack. Fails with `StackOverflow` instead of wrapping around when the
+    /// word doesn't fit below the top of the stack region.
     fn write_u32_to_ram(
         &mut self,
         value: u32,
-    ) {
-        let value = value.to_le_bytes();
-        for i in 0..4 {
-            self.write_byte(self.stack_pointer, value[i]);
-            self.advance_sp();
+    ) -> Result<(), CpuError> {
+        if self.stack_pointer as u64 + 4 > self.stack_top() {
+            return Err(CpuError::new(
+                self.program_counter,
+                self.instruction_address,
+                self.stack_pointer,
+                self.registers,
+                CpuErrorType::StackOverflow,
+                self.index,
+                self.instructions_retired,
+            ));
         }
+        // The bounds check above guarantees the word doesn't wrap, so it is written with a single
+        // bus access instead of locking the bus once per byte
+        let start = self.stack_pointer;
+        self.write_word(start, value)?;
+        self.stack_pointer += 4;
         info!(
             "Stored {:032b} to RAM at addresses 0x{:08X} - 0x{:08X}",
-            u32::from_le_bytes(value),
-            self.stack_pointer,
-            self.stack_pointer + 4
+            value,
+            start,
+            start + 4
         );
+        Ok(())
     }
 
+    /// Reads the last word from the stack and moves the stack pointer below it. Fails with
+    /// `StackOpOutOfBounds` when that would leave the stack region.
     fn read_u32_from_ram(
         &mut self,
-    ) -> u32 {
-        let mut value: [u8; 4] = [0; 4];
-        for i in 0..4 {
-            self.decrease_sp();
-            value[i] = self.read_byte(self.stack_pointer);
+    ) -> Result<u32, CpuError> {
+        if (self.stack_pointer as u64) < self.stack_base as u64 + 4 {
+            return Err(CpuError::new(
+                self.program_counter,
+                self.instruction_address,
+                self.stack_pointer,
+                self.registers,
+                CpuErrorType::StackOpOutOfBounds,
+                self.index,
+                self.instructions_retired,
+            ));
         }
+        let value = self.read_word(self.stack_pointer - 4)?;
+        self.stack_pointer -= 4;
         info!(
             "Read u32 {:032b} from RAM at addresses 0x{:08X} - 0x{:08X}",
-            u32::from_be_bytes(value),
+            value,
             self.stack_pointer,
             self.stack_pointer + 4
         );
-        return u32::from_be_bytes(value);
+        return Ok(value);
     }
 
     fn pop_u32_from_ram(
         &mut self,
-    ) -> u32 {
-        let value = self.read_u32_from_ram();
-        for _ in 0..4 {
-            self.bus.write().unwrap().write8(self.stack_pointer, 0);
-        }
+    ) -> Result<u32, CpuError> {
+        let value = self.read_u32_from_ram()?;
+        self.write_word(self.stack_pointer, 0)?;
         info!(
             "Read u32 {:032b} from RAM at addresses 0x{:08X} - 0x{:08X}",
             value,
             self.stack_pointer,
             self.stack_pointer + 4
         );
-        return value;
+        return Ok(value);
     }
 
+    /// Reads the instruction at the program counter and advances past it. Fails with
+    /// `MemoryFault` if the program counter ran outside of the memory, the program counter is
+    /// advanced anyway so modes that ignore the error don't fetch the same word again.
     fn fetch_u32(
         &mut self,
-    ) -> u32 {
+    ) -> Result<u32, CpuError> {
+        let address = self.program_counter;
+        self.program_counter = address.wrapping_add(4);
+        self.check_bounds(address, 4)?;
+        let bus = self.bus.read().unwrap();
         let instruction = u32::from_le_bytes([
-            self.bus.read().unwrap().read8(self.program_counter + 0),
-            self.bus.read().unwrap().read8(self.program_counter + 1),
-            self.bus.read().unwrap().read8(self.program_counter + 2),
-            self.bus.read().unwrap().read8(self.program_counter + 3),
+            bus.read8(address),
+            bus.read8(address + 1),
+            bus.read8(address + 2),
+            bus.read8(address + 3),
         ]);
-        self.program_counter += 4;
-        return instruction
+        return Ok(instruction)
     }
 
     pub fn handle_interrupts(
         &mut self,
         interrupt: Interrupt,
-    ) {
+    ) -> Result<(), CpuError> {
         info!(
-            core = self.index,
             "Core {} received {}", self.index, interrupt
         );
         match interrupt.interrupt_type {
-            InterruptType::Halt => self.halted = false,
-            InterruptType::Resume => self.halted = true,
-            InterruptType::SoftReset => self.reset_soft(),
-            InterruptType::HardReset => self.reset_hard(),
+            InterruptType::Halt => self.halted = true,
+            InterruptType::Resume => {
+                self.halted = false;
+                if !self.busy {
+                    self.reset_soft()?;
+                    self.busy = true;
+                }
+            }
+            InterruptType::SoftReset => self.reset_soft()?,
+            InterruptType::HardReset => self.reset_hard()?,
+            InterruptType::Irq(vector) => {
+                if !self.pending_irqs.contains(&vector) {
+                    self.pending_irqs.push_back(vector);
+                }
+            }
+            InterruptType::Data => {
+                if let Some(payload) = interrupt.payload {
+                    self.registers[31] = payload;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles all pending interrupts. While the core is halted or idle this blocks until an
+    /// interrupt resumes it or the VM stops running. If IRQs are enabled, the oldest pending IRQ
+    /// is entered afterwards.
+    pub fn poll_interrupts(
+        &mut self,
+    ) -> Result<(), CpuError> {
+        self.receive_interrupts()?;
+        while (self.halted || !self.busy) && self.running.load(std::sync::atomic::Ordering::Relaxed) {
+            match self.receiver.recv_timeout(std::time::Duration::from_millis(100)) {
+                Ok(interrupt) => self.handle_interrupts(interrupt)?,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        self.enter_pending_irq()
+    }
+
+    /// Handles every interrupt that was already sent to this core, without waiting for more.
+    pub fn receive_interrupts(
+        &mut self,
+    ) -> Result<(), CpuError> {
+        while let Ok(interrupt) = self.receiver.try_recv() {
+            self.handle_interrupts(interrupt)?;
+        }
+        Ok(())
+    }
+
+    /// Enters the handler of the oldest pending IRQ if the core is executing and IRQs are enabled.
+    pub fn enter_pending_irq(
+        &mut self,
+    ) -> Result<(), CpuError> {
+        if self.interrupts_enabled
+            && !self.halted
+            && self.busy
+            && let Some(vector) = self.pending_irqs.pop_front()
+        {
+            self.instruction_address = self.program_counter;
+            self.enter_irq(vector)?;
+        }
+        Ok(())
+    }
+
+    /// Sleeps for one full clock cycle if the core runs at a fixed clock rate. The time spent
+    /// executing the instruction is not subtracted.
+    pub fn wait_for_next_cycle(&self) {
+        if let Some(duration) = self.cycle_duration {
+            std::thread::sleep(duration);
+        }
+    }
+
+    /// Pushes the program counter and jumps to the handler of `vector`. Handler addresses are
+    /// little-endian u32 entries of the interrupt vector table, so the handler of vector `n` is read
+    /// from `interrupt_vector_table + n * 4`. Handlers return with `RTRN` or `RTRN_POP`. Vectors
+    /// whose entry lies beyond the 32-bit address space fail with `MemoryFault` before anything is
+    /// pushed.
+    fn enter_irq(
+        &mut self,
+        vector: u32,
+    ) -> Result<(), CpuError> {
+        let Some(entry) = vector.checked_mul(4).and_then(|offset| offset.checked_add(self.interrupt_vector_table)) else {
+            return Err(CpuError::new(
+                self.program_counter,
+                self.instruction_address,
+                self.stack_pointer,
+                self.registers,
+                CpuErrorType::MemoryFault(vector),
+                self.index,
+                self.instructions_retired,
+            ));
+        };
+        self.check_bounds(entry, 4)?;
+        self.write_u32_to_ram(self.program_counter)?;
+        let addr = self.read_word(entry)?;
+        info!("Entering handler for IRQ {} at 0x{:08X}", vector, addr);
+        self.program_counter = addr;
+        Ok(())
+    }
+
+    /// Disassembles the instruction just fetched from `instruction_address`, including the
+    /// constant that follows a `LOAD_FULL`.
+    fn disassemble_current(&self, instruction: u32) -> String {
+        let mut words = vec![instruction];
+        if OpCode::decode(instruction) == Ok(OpCode::LOAD_FULL) {
+            words.extend(self.read_word(self.program_counter).ok());
         }
+        crate::disasm::listing(&words, self.instruction_address).remove(0).1
     }
 
     pub fn tick(
         &mut self,
     ) -> Result<(), CpuError> {
-        let instruction = self.fetch_u32();
-        let opcode_val = (instruction >> 25) & 0x7F;
-        let opcode: OpCode = match TryFrom::try_from(opcode_val) {
-            Ok(val) => val,
+        self.instruction_address = self.program_counter;
+        let instruction = self.fetch_u32()?;
+        if self.step {
+            let disassembly = self.disassemble_current(instruction);
+            crate::cpu::wait_for_enter(|| {
+                info!(
+                    "\nNext: 0x{:08X}: {}\nStack Pointer: 0x{:08X}\nRegisters: {:?}\nPress ENTER to execute",
+                    self.instruction_address,
+                    disassembly,
+                    self.stack_pointer,
+                    self.registers
+                );
+            });
+        }
+        let opcode_val = instruction >> encoding::OPCODE_SHIFT;
+        let opcode = match OpCode::decode(instruction) {
+            Ok(opcode) => opcode,
             Err(_) => {
                 return Err(CpuError::new(
                     self.program_counter,
+                    self.instruction_address,
                     self.stack_pointer,
                     self.registers,
                     CpuErrorType::InvalidOpCode(opcode_val),
                     self.index,
+                    self.instructions_retired,
                 ));
             }
         };
-        info!(
-            core = self.index,
-            "0x{:08X}: 0x{:02X} - {}",
-            self.program_counter - 4,
-            opcode_val,
-            opcode
-        );
+        // Disassembling allocates and may read the bus again, so it is skipped while nothing logs it
+        if tracing::enabled!(tracing::Level::INFO) {
+            let disassembly = self.disassemble_current(instruction);
+            info!(
+                pc = self.instruction_address,
+                opcode = %opcode,
+                operands = disassembly.split_once(' ').map_or("", |(_, operands)| operands),
+                "0x{:08X}: 0x{:02X} - {}",
+                self.instruction_address,
+                opcode_val,
+                disassembly
+            );
+        }
         match opcode {
             OpCode::LOAD_IMM => {
-                let rde = (instruction >> 20) & 0x1F;
-                let value = instruction & 0xFFFFF;
+                let rde = encoding::field_a(instruction);
+                let value = encoding::imm20(instruction);
                 self.registers[rde as usize] = value;
-                info!(core=?self.index, "Loaded value {} into register {}", self.registers[rde as usize], rde);
+                info!("Loaded value {} into register {}", self.registers[rde as usize], rde);
+            }
+            OpCode::LOAD_FULL => {
+                let rde = encoding::field_a(instruction);
+                self.registers[rde as usize] = self.fetch_u32()?;
+                info!("Loaded value {} into register {}", self.registers[rde as usize], rde);
             }
             OpCode::LDUP_IMM => {
-                let rde = (instruction >> 20) & 0x1F;
-                let value = instruction & 0xFFFFF;
-                self.registers[rde as usize] = value << 12;
-                info!(core=?self.index, "Loaded value {} into register {}", self.registers[rde as usize], rde);
+                let rde = encoding::field_a(instruction);
+                let value = encoding::imm20(instruction);
+                self.registers[rde as usize] = value << 12 | self.registers[rde as usize] & 0xFFF;
+                info!("Loaded value {} into register {}", self.registers[rde as usize], rde);
+            }
+            OpCode::STOR_IMM => {
+                let rs1 = encoding::field_a(instruction);
+                let addr = encoding::imm20(instruction);
+                let value = self.registers[rs1 as usize];
+                info!("Writing value {} to 0x{:08X}", value, addr);
+                self.write_word(addr, value)?;
             }
             OpCode::LOAD_BYTE => {
-                let rde = (instruction >> 20) & 0x1F;
-                let addr = (instruction >> 15) & 0x1F;
-                let value = self.read_byte(addr);
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let addr = self.registers[rs1 as usize];
+                let value = self.read_byte(addr)?;
                 self.registers[rde as usize] = value as u32;
-                info!(core=?self.index, "Read value {} from 0x{:08X}", value, addr);
+                info!("Read value {} from 0x{:08X}", value, addr);
+            }
+            OpCode::LOAD_WORD => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let addr = self.registers[rs1 as usize];
+                let value = self.read_word(addr)?;
+                self.registers[rde as usize] = value;
+                info!("Read word {} from 0x{:08X}", value, addr);
+            }
+            OpCode::LOAD_HALF => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let addr = self.registers[rs1 as usize];
+                let value = self.read_half(addr)?;
+                self.registers[rde as usize] = value as u32;
+                info!("Read halfword {} from 0x{:08X}", value, addr);
+            }
+            OpCode::LOAD_HALF_S => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let addr = self.registers[rs1 as usize];
+                let value = sign_extend(self.read_half(addr)? as u32, 16);
+                self.registers[rde as usize] = value;
+                info!("Read signed halfword {} from 0x{:08X}", value as i32, addr);
+            }
+            OpCode::STOR_HALF => {
+                let addr = self.registers[(encoding::field_a(instruction)) as usize];
+                let value = self.registers[(encoding::field_b(instruction)) as usize];
+                info!("Writing halfword {} to 0x{:08X}", value as u16, addr);
+                self.write_half(addr, value as u16)?;
             }
             OpCode::STOR_BYTE => {
-                let addr = self.registers[((instruction >> 20) & 0x1F) as usize];
-                let value = self.registers[((instruction >> 15) & 0x1F) as usize];
-                info!(core=?self.index, "Writing value {} to 0x{:08X}", value, addr);
-                self.write_byte(addr, value as u8);
+                let addr = self.registers[(encoding::field_a(instruction)) as usize];
+                let value = self.registers[(encoding::field_b(instruction)) as usize];
+                info!("Writing value {} to 0x{:08X}", value, addr);
+                self.write_byte(addr, value as u8)?;
+            }
+            OpCode::MOV => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                info!("Copying register {} to register {}", rs1, rde);
+                self.registers[rde as usize] = self.registers[rs1 as usize];
+            }
+            OpCode::CLR => {
+                let rde = encoding::field_a(instruction);
+                info!("Clearing register {}", rde);
+                self.registers[rde as usize] = 0;
+            }
+            OpCode::COREID => {
+                let rde = encoding::field_a(instruction);
+                info!("Loading core index {} to register {}", self.index, rde);
+                self.registers[rde as usize] = self.index;
+            }
+            OpCode::YIELD => {
+                let millis = encoding::imm25(instruction);
+                if millis == 0 {
+                    std::thread::yield_now();
+                } else {
+                    info!("Yielding for {} ms", millis);
+                    // Slept in slices so a VM that is stopping doesn't wait for up to 9 hours
+                    let deadline = std::time::Instant::now() + std::time::Duration::from_millis(millis as u64);
+                    while self.running.load(std::sync::atomic::Ordering::Relaxed) {
+                        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                        if remaining.is_zero() {
+                            break;
+                        }
+                        std::thread::sleep(remaining.min(std::time::Duration::from_millis(10)));
+                    }
+                }
+            }
+            OpCode::RDSP => {
+                let rde = encoding::field_a(instruction);
+                info!("Copying stack pointer 0x{:08X} to register {}", self.stack_pointer, rde);
+                self.registers[rde as usize] = self.stack_pointer;
+            }
+            OpCode::WRSP => {
+                let rs1 = encoding::field_a(instruction);
+                let value = self.registers[rs1 as usize];
+                if !(self.stack_base as u64..=self.stack_top()).contains(&(value as u64)) {
+                    return Err(CpuError::new(
+                        self.program_counter,
+                        self.instruction_address,
+                        self.stack_pointer,
+                        self.registers,
+                        CpuErrorType::StackOpOutOfBounds,
+                        self.index,
+                        self.instructions_retired,
+                    ));
+                }
+                info!("Copying register {} to stack pointer 0x{:08X}", rs1, value);
+                self.stack_pointer = value;
+            }
+            OpCode::RDPC => {
+                let rde = encoding::field_a(instruction);
+                info!("Copying program counter 0x{:08X} to register {}", self.program_counter, rde);
+                self.registers[rde as usize] = self.program_counter;
             }
             OpCode::JUMP_IMM => {
-                let addr = (instruction >> 20) & 0x1FFFFFF;
-                info!(core=?self.index, "Jumping to address 0x{:08X}", addr);
+                let addr = encoding::imm25(instruction);
+                info!("Jumping to address 0x{:08X}", addr);
                 self.program_counter = addr;
             }
             OpCode::JUMP_REG => {
-                let rs1 = (instruction >> 20) & 0x1F;
-                info!(core=?self.index, "Jumping to address 0x{:08X}", self.registers[rs1 as usize]);
+                let rs1 = encoding::field_a(instruction);
+                self.check_bounds(self.registers[rs1 as usize], 4)?;
+                info!("Jumping to address 0x{:08X}", self.registers[rs1 as usize]);
                 self.program_counter = self.registers[rs1 as usize];
             }
             OpCode::BRAN_IMM => {
-                self.write_u32_to_ram(self.program_counter);
-                let addr = instruction & 0x1FFFFFF;
-                info!(core=?self.index, "Branching to address 0x{:08X}", addr);
+                self.write_u32_to_ram(self.program_counter)?;
+                let addr = encoding::imm25(instruction);
+                info!("Branching to address 0x{:08X}", addr);
                 self.program_counter = addr;
             }
             OpCode::BRAN_REG => {
-                self.write_u32_to_ram(self.program_counter);
-                let rs1 = (instruction >> 20) & 0x1F;
-                info!(core=?self.index, "branching to address 0x{:08X}", self.registers[rs1 as usize]);
+                let rs1 = encoding::field_a(instruction);
+                self.check_bounds(self.registers[rs1 as usize], 4)?;
+                self.write_u32_to_ram(self.program_counter)?;
+                info!("branching to address 0x{:08X}", self.registers[rs1 as usize]);
                 self.program_counter = self.registers[rs1 as usize];
             }
             OpCode::JUEQ_REG => {
-                let rs1 = (instruction >> 20) & 0x1F;
-                let rs2 = (instruction >> 15) & 0x1F;
-                let rs3 = (instruction >> 10) & 0x1F;
-                info!(core=?self.index, "Comparing register {} ({}) with register {} ({})...", rs1, self.registers[rs1 as usize], rs2, self.registers[rs2 as usize]);
+                let rs1 = encoding::field_a(instruction);
+                let rs2 = encoding::field_b(instruction);
+                let rs3 = encoding::field_c(instruction);
+                info!("Comparing register {} ({}) with register {} ({})...", rs1, self.registers[rs1 as usize], rs2, self.registers[rs2 as usize]);
                 if self.registers[rs1 as usize] ^ self.registers[rs2 as usize] == 0 {
-                    info!(core=?self.index, "Jumping to address 0x{:08X}", self.registers[rs3 as usize]);
+                    self.check_bounds(self.registers[rs3 as usize], 4)?;
+                    info!("Jumping to address 0x{:08X}", self.registers[rs3 as usize]);
                     self.program_counter = self.registers[rs3 as usize];
                 }
             }
             OpCode::BREQ_REG => {
-                let rs1 = (instruction >> 20) & 0x1F;
-                let rs2 = (instruction >> 15) & 0x1F;
-                let rs3 = (instruction >> 10) & 0x1F;
-                info!(core=?self.index, "Comparing register {} ({}) with register {} ({})...", rs1, self.registers[rs1 as usize], rs2, self.registers[rs2 as usize]);
+                let rs1 = encoding::field_a(instruction);
+                let rs2 = encoding::field_b(instruction);
+                let rs3 = encoding::field_c(instruction);
+                info!("Comparing register {} ({}) with register {} ({})...", rs1, self.registers[rs1 as usize], rs2, self.registers[rs2 as usize]);
                 if self.registers[rs1 as usize] ^ self.registers[rs2 as usize] == 0 {
-                    info!(core=?self.index, "Branching to address 0x{:08X}", self.registers[rs3 as usize]);
-                    self.write_u32_to_ram(self.program_counter);
+                    self.check_bounds(self.registers[rs3 as usize], 4)?;
+                    info!("Branching to address 0x{:08X}", self.registers[rs3 as usize]);
+                    self.write_u32_to_ram(self.program_counter)?;
                     self.program_counter = self.registers[rs3 as usize];
                 }
             }
-            OpCode::JUMP_REL => {
-                let sign = (instruction >> 24) & 0x1;
-                let val = instruction & 0xFFFFFF;
-                match sign {
-                    1 => {
-                        info!(core=?self.index, "Increasing program counter by {}", val);
-                        self.program_counter += val
-                    }
-                    0 => {
-                        info!(core=?self.index, "Decreasing program counter by {}", val);
-                        if val > self.program_counter {
-                            error!(core=?self.index, "Decrement larger than program counter value, setting to 0 instead");
-                            self.program_counter = 0;
-                        } else {
-                            self.program_counter -= val
-                        }
-                    }
-                    _ => {}
+            OpCode::BEQ_IMM => {
+                let addr = encoding::imm25(instruction);
+                if self.eq_flag {
+                    info!("Equality flag set, jumping to address 0x{:08X}", addr);
+                    self.program_counter = addr;
                 }
             }
-            OpCode::BRAN_REL => {
-                let sign = (instruction >> 24) & 0x1;
-                let val = instruction & 0xFFFFFF;
-                self.write_u32_to_ram(self.program_counter);
-                match sign {
-                    1 => {
-                        info!(core=?self.index, "Increasing program counter by {}", val);
-                        self.program_counter += val
-                    }
-                    0 => {
-                        info!(core=?self.index, "Decreasing program counter by {}", val);
-                        if val > self.program_counter {
-                            error!(core=?self.index, "Decrement larger than program counter value, setting to 0 instead");
-                            self.program_counter = 0;
-                        } else {
-                            self.program_counter -= val
-                        }
-                    }
-                    _ => {}
+            OpCode::BNE_IMM => {
+                let addr = encoding::imm25(instruction);
+                if !self.eq_flag {
+                    info!("Equality flag not set, jumping to address 0x{:08X}", addr);
+                    self.program_counter = addr;
+                }
+            }
+            OpCode::BLT_IMM => {
+                let addr = encoding::imm25(instruction);
+                if self.lt_flag {
+                    info!("Less-than flag set, jumping to address 0x{:08X}", addr);
+                    self.program_counter = addr;
                 }
             }
+            OpCode::BGE_IMM => {
+                let addr = encoding::imm25(instruction);
+                if !self.lt_flag {
+                    info!("Less-than flag not set, jumping to address 0x{:08X}", addr);
+                    self.program_counter = addr;
+                }
+            }
+            OpCode::JUMP_REL => {
+                let offset = sign_extend(encoding::imm25(instruction), 25);
+                info!("Offsetting program counter by {}", offset as i32);
+                self.program_counter = self.program_counter.wrapping_add(offset);
+            }
+            OpCode::CALL_REL => {
+                let offset = sign_extend(encoding::imm25(instruction), 25);
+                self.write_u32_to_ram(self.program_counter)?;
+                info!("Calling subroutine at offset {}", offset as i32);
+                self.program_counter = self.program_counter.wrapping_add(offset);
+            }
+            OpCode::PUSH => {
+                let rs1 = encoding::field_a(instruction);
+                info!("Pushing register {} ({}) onto the stack", rs1, self.registers[rs1 as usize]);
+                self.write_u32_to_ram(self.registers[rs1 as usize])?;
+            }
+            OpCode::POP => {
+                let rde = encoding::field_a(instruction);
+                self.registers[rde as usize] = self.pop_u32_from_ram()?;
+                info!("Popped {} from the stack into register {}", self.registers[rde as usize], rde);
+            }
             OpCode::RTRN => {
-                let addr = self.read_u32_from_ram();
-                info!(core=?self.index, "Returning to address 0x{:08X}", addr);
+                let addr = self.read_u32_from_ram()?;
+                info!("Returning to address 0x{:08X}", addr);
                 self.program_counter = addr;
             }
             OpCode::RTRN_POP => {
-                let addr = self.pop_u32_from_ram();
-                info!(core=?self.index, "Returning to address 0x{:08X}", addr);
+                let addr = self.pop_u32_from_ram()?;
+                info!("Returning to address 0x{:08X}", addr);
                 self.program_counter = addr;
             }
+            OpCode::CMP => {
+                let rs1 = encoding::field_a(instruction);
+                let rs2 = encoding::field_b(instruction);
+                self.eq_flag = self.registers[rs1 as usize] == self.registers[rs2 as usize];
+                info!("Compared register {} ({}) with register {} ({}), equality flag is {}", rs1, self.registers[rs1 as usize], rs2, self.registers[rs2 as usize], self.eq_flag);
+            }
+            OpCode::CMP_LT => {
+                let rs1 = encoding::field_a(instruction);
+                let rs2 = encoding::field_b(instruction);
+                let lhs = self.registers[rs1 as usize] as i32;
+                let rhs = self.registers[rs2 as usize] as i32;
+                self.lt_flag = lhs < rhs;
+                info!("Compared register {} ({}) with register {} ({}) as signed values, less-than flag is {}", rs1, lhs, rs2, rhs, self.lt_flag);
+            }
+            OpCode::CMP_LTU => {
+                let rs1 = encoding::field_a(instruction);
+                let rs2 = encoding::field_b(instruction);
+                self.lt_flag = self.registers[rs1 as usize] < self.registers[rs2 as usize];
+                info!("Compared register {} ({}) with register {} ({}) as unsigned values, less-than flag is {}", rs1, self.registers[rs1 as usize], rs2, self.registers[rs2 as usize], self.lt_flag);
+            }
+            OpCode::CAS => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                let addr = self.registers[rs1 as usize];
+                self.check_bounds(addr, 4)?;
+                // Holding the bus exclusively for the read and the write keeps other cores out
+                let mut bus = self.bus.write().unwrap();
+                let current = bus.read32(addr);
+                self.eq_flag = current == self.registers[rs2 as usize];
+                if self.eq_flag {
+                    bus.write32(addr, self.registers[rde as usize]);
+                }
+                info!("Compared 0x{:08X} ({}) with register {} ({}), swapped in register {} ({}): {}", addr, current, rs2, self.registers[rs2 as usize], rde, self.registers[rde as usize], self.eq_flag);
+            }
+            OpCode::BTST => {
+                let rs1 = encoding::field_a(instruction);
+                let bit = encoding::field_b(instruction);
+                self.eq_flag = (self.registers[rs1 as usize] >> bit) & 1 == 1;
+                info!("Tested bit {} of register {} ({}), equality flag is {}", bit, rs1, self.registers[rs1 as usize], self.eq_flag);
+            }
             OpCode::ORR => {
-                let rde = (instruction >> 20) & 0x1F;
-                let rs1 = (instruction >> 15) & 0x1F;
-                let rs2 = (instruction >> 10) & 0x1F;
-                info!(core=?self.index, "OR-ing register {} and register {}, storing in register {}", rs1, rs2, rde);
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("OR-ing register {} and register {}, storing in register {}", rs1, rs2, rde);
                 self.registers[rde as usize] =
                     self.registers[rs1 as usize] | self.registers[rs2 as usize];
             }
             OpCode::ORI => {
-                let rde = (instruction >> 20) & 0x1F;
-                let value = instruction & 0xFFFFF;
-                info!(core=?self.index, "OR-ing register {} with immediate value {}, storing in register {}", rde, value, rde);
+                let rde = encoding::field_a(instruction);
+                let value = encoding::imm20(instruction);
+                info!("OR-ing register {} with immediate value {}, storing in register {}", rde, value, rde);
                 self.registers[rde as usize] = self.registers[rde as usize] | value;
             }
             OpCode::XOR => {
-                let rde = (instruction >> 20) & 0x1F;
-                let rs1 = (instruction >> 15) & 0x1F;
-                let rs2 = (instruction >> 10) & 0x1F;
-                info!(core=?self.index, "XOR-ing register {} and register {}, storing in register {}", rs1, rs2, rde);
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("XOR-ing register {} and register {}, storing in register {}", rs1, rs2, rde);
                 self.registers[rde as usize] =
                     self.registers[rs1 as usize] ^ self.registers[rs2 as usize];
             }
             OpCode::AND => {
-                let rde = (instruction >> 20) & 0x1F;
-                let rs1 = (instruction >> 15) & 0x1F;
-                let rs2 = (instruction >> 10) & 0x1F;
-                info!(core=?self.index, "AND-ing register {} and register {}, storing in register {}", rs1, rs2, rde);
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("AND-ing register {} and register {}, storing in register {}", rs1, rs2, rde);
                 self.registers[rde as usize] =
                     self.registers[rs1 as usize] & self.registers[rs2 as usize];
             }
+            OpCode::NOT => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                info!("Inverting register {}, storing in register {}", rs1, rde);
+                self.registers[rde as usize] = !self.registers[rs1 as usize];
+            }
+            OpCode::NAND => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("NAND-ing register {} and register {}, storing in register {}", rs1, rs2, rde);
+                self.registers[rde as usize] =
+                    !(self.registers[rs1 as usize] & self.registers[rs2 as usize]);
+            }
+            OpCode::NOR => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("NOR-ing register {} and register {}, storing in register {}", rs1, rs2, rde);
+                self.registers[rde as usize] =
+                    !(self.registers[rs1 as usize] | self.registers[rs2 as usize]);
+            }
+            OpCode::SHL => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                let amount = self.registers[rs2 as usize] & 0x1F;
+                info!("Shifting register {} left by {}, storing in register {}", rs1, amount, rde);
+                self.registers[rde as usize] = self.registers[rs1 as usize].wrapping_shl(amount);
+            }
+            OpCode::SHR => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                let amount = self.registers[rs2 as usize] & 0x1F;
+                info!("Shifting register {} right by {}, storing in register {}", rs1, amount, rde);
+                self.registers[rde as usize] = self.registers[rs1 as usize].wrapping_shr(amount);
+            }
+            OpCode::SAR => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                let amount = self.registers[rs2 as usize] & 0x1F;
+                info!("Arithmetically shifting register {} right by {}, storing in register {}", rs1, amount, rde);
+                self.registers[rde as usize] =
+                    (self.registers[rs1 as usize] as i32).wrapping_shr(amount) as u32;
+            }
             OpCode::ADD => {
-                let rde = (instruction >> 20) & 0x1F;
-                let rs1 = (instruction >> 15) & 0x1F;
-                let rs2 = (instruction >> 10) & 0x1F;
-                info!(core=?self.index, "Adding register {} and register {}, storing in register {}", rs1, rs2, rde);
-                let value =
-                    (self.registers[rs1 as usize] as u64) + (self.registers[rs2 as usize] as u64);
-                if value > u32::MAX.into() {
-                    self.registers[rde as usize] = (value >> 1) as u32;
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("Adding register {} and register {}, storing in register {}", rs1, rs2, rde);
+                let (lhs, rhs) = (self.registers[rs1 as usize], self.registers[rs2 as usize]);
+                let (result, overflow) = lhs.overflowing_add(rhs);
+                self.carry_flag = overflow;
+                self.overflow_flag = (lhs as i32).overflowing_add(rhs as i32).1;
+                self.registers[rde as usize] = result;
+                if overflow && self.arith == ArithMode::Checked {
                     return Err(CpuError::new(
                         self.program_counter,
+                        self.instruction_address,
                         self.stack_pointer,
                         self.registers,
                         CpuErrorType::AddWithOverflow,
                         self.index,
+                        self.instructions_retired,
                     ));
-                } else {
-                    self.registers[rde as usize] = value as u32;
                 }
             }
             OpCode::SUB => {
-                let rde = (instruction >> 20) & 0x1F;
-                let rs1 = (instruction >> 15) & 0x1F;
-                let rs2 = (instruction >> 10) & 0x1F;
-                info!(core=?self.index, "Subtracting register {} from register {}, storing in register {}", rs2, rs1, rde);
-                if self.registers[rs1 as usize] >= self.registers[rs2 as usize] {
-                    self.registers[rde as usize] =
-                        self.registers[rs1 as usize] - self.registers[rs2 as usize];
-                } else {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("Subtracting register {} from register {}, storing in register {}", rs2, rs1, rde);
+                let (lhs, rhs) = (self.registers[rs1 as usize], self.registers[rs2 as usize]);
+                let (result, overflow) = lhs.overflowing_sub(rhs);
+                self.carry_flag = overflow;
+                self.overflow_flag = (lhs as i32).overflowing_sub(rhs as i32).1;
+                if overflow && self.arith == ArithMode::Checked {
                     return Err(CpuError::new(
                         self.program_counter,
+                        self.instruction_address,
                         self.stack_pointer,
                         self.registers,
                         CpuErrorType::SubWithOverflow,
                         self.index,
+                        self.instructions_retired,
+                    ));
+                }
+                self.registers[rde as usize] = result;
+            }
+            OpCode::ADC => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("Adding register {} and register {} with carry {}, storing in register {}", rs1, rs2, self.carry_flag, rde);
+                let (lhs, rhs, carry) = (self.registers[rs1 as usize], self.registers[rs2 as usize], self.carry_flag as u32);
+                let (partial, carry_out1) = lhs.overflowing_add(rhs);
+                let (result, carry_out2) = partial.overflowing_add(carry);
+                let signed = lhs as i32 as i64 + rhs as i32 as i64 + carry as i64;
+                self.carry_flag = carry_out1 || carry_out2;
+                self.overflow_flag = signed != result as i32 as i64;
+                self.registers[rde as usize] = result;
+            }
+            OpCode::SBC => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("Subtracting register {} and borrow {} from register {}, storing in register {}", rs2, self.carry_flag, rs1, rde);
+                let (lhs, rhs, borrow) = (self.registers[rs1 as usize], self.registers[rs2 as usize], self.carry_flag as u32);
+                let (partial, borrow_out1) = lhs.overflowing_sub(rhs);
+                let (result, borrow_out2) = partial.overflowing_sub(borrow);
+                let signed = lhs as i32 as i64 - rhs as i32 as i64 - borrow as i64;
+                self.carry_flag = borrow_out1 || borrow_out2;
+                self.overflow_flag = signed != result as i32 as i64;
+                self.registers[rde as usize] = result;
+            }
+            OpCode::ADDI => {
+                let rde = encoding::field_a(instruction);
+                let value = encoding::imm20(instruction);
+                info!("Adding immediate value {} to register {}", value, rde);
+                let (result, overflow) = self.registers[rde as usize].overflowing_add(value);
+                self.registers[rde as usize] = result;
+                if overflow && self.arith == ArithMode::Checked {
+                    return Err(CpuError::new(
+                        self.program_counter,
+                        self.instruction_address,
+                        self.stack_pointer,
+                        self.registers,
+                        CpuErrorType::AddWithOverflow,
+                        self.index,
+                        self.instructions_retired,
                     ));
                 }
             }
+            OpCode::SUBI => {
+                let rde = encoding::field_a(instruction);
+                let value = encoding::imm20(instruction);
+                info!("Subtracting immediate value {} from register {}", value, rde);
+                let (result, overflow) = self.registers[rde as usize].overflowing_sub(value);
+                if overflow && self.arith == ArithMode::Checked {
+                    return Err(CpuError::new(
+                        self.program_counter,
+                        self.instruction_address,
+                        self.stack_pointer,
+                        self.registers,
+                        CpuErrorType::SubWithOverflow,
+                        self.index,
+                        self.instructions_retired,
+                    ));
+                }
+                self.registers[rde as usize] = result;
+            }
+            OpCode::MUL => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("Multiplying register {} and register {}, storing in register {}", rs1, rs2, rde);
+                let value =
+                    (self.registers[rs1 as usize] as u64) * (self.registers[rs2 as usize] as u64);
+                self.registers[rde as usize] = value as u32;
+                if value > u32::MAX.into() {
+                    return Err(CpuError::new(
+                        self.program_counter,
+                        self.instruction_address,
+                        self.stack_pointer,
+                        self.registers,
+                        CpuErrorType::MulWithOverflow,
+                        self.index,
+                        self.instructions_retired,
+                    ));
+                }
+            }
+            OpCode::DIV => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("Dividing register {} by register {}, storing in register {}", rs1, rs2, rde);
+                if self.registers[rs2 as usize] == 0 {
+                    return Err(CpuError::new(
+                        self.program_counter,
+                        self.instruction_address,
+                        self.stack_pointer,
+                        self.registers,
+                        CpuErrorType::DivisionByZero,
+                        self.index,
+                        self.instructions_retired,
+                    ));
+                }
+                self.registers[rde as usize] =
+                    self.registers[rs1 as usize] / self.registers[rs2 as usize];
+            }
+            OpCode::MOD => {
+                let rde = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let rs2 = encoding::field_c(instruction);
+                info!("Taking the remainder of register {} divided by register {}, storing in register {}", rs1, rs2, rde);
+                if self.registers[rs2 as usize] == 0 {
+                    return Err(CpuError::new(
+                        self.program_counter,
+                        self.instruction_address,
+                        self.stack_pointer,
+                        self.registers,
+                        CpuErrorType::DivisionByZero,
+                        self.index,
+                        self.instructions_retired,
+                    ));
+                }
+                self.registers[rde as usize] =
+                    self.registers[rs1 as usize] % self.registers[rs2 as usize];
+            }
             OpCode::NOOP => {}
-            OpCode::RSET_SOFT => self.reset_soft(),
-            OpCode::RSET_HARD => self.reset_hard(),
+            OpCode::RSET_SOFT => self.reset_soft()?,
+            OpCode::RSET_HARD => self.reset_hard()?,
+            OpCode::SHUTDOWN => {
+                info!("Shutting down the VM");
+                self.running.store(false, std::sync::atomic::Ordering::Relaxed);
+            }
             OpCode::HALT => {
+                info!("Halting until resumed");
+                self.halted = true;
                 return Err(CpuError::new(
                     self.program_counter,
+                    self.instruction_address,
                     self.stack_pointer,
                     self.registers,
                     CpuErrorType::Halt,
                     self.index,
+                    self.instructions_retired,
                 ));
             }
+            OpCode::CLI => {
+                info!("Disabling IRQs");
+                self.interrupts_enabled = false;
+            }
+            OpCode::SEI => {
+                info!("Enabling IRQs");
+                self.interrupts_enabled = true;
+            }
+            OpCode::CLC => {
+                info!("Clearing carry flag");
+                self.carry_flag = false;
+            }
             OpCode::IRPT_SEND => {
-                let target_idx = (instruction >> 20) & 0x1F;
-                let itype_val = (instruction >> 15) & 0x1F;
-
-                if let Some(target_sender) = self.senders.get(target_idx as usize) {
-                    let msg = Interrupt {
-                        sender_id: self.index,
-                        interrupt_type: match itype_val {
-                            1 => InterruptType::Resume,
-                            2 => InterruptType::Halt,
-                            3 => InterruptType::SoftReset,
-                            4 => InterruptType::HardReset,
-                            _ => panic!("Unknown Interrupt: {}", itype_val),
-                        },
-                    };
-                    info!(core=?self.index, "Sent {} to Core {}", msg, target_idx);
+                let target_idx = encoding::field_a(instruction);
+                let itype_val = encoding::field_b(instruction);
+                let vector = encoding::field_c(instruction);
+
+                let Some(interrupt_type) = InterruptType::from_code(itype_val, vector) else {
+                    return Err(CpuError::new(
+                        self.program_counter,
+                        self.instruction_address,
+                        self.stack_pointer,
+                        self.registers,
+                        CpuErrorType::InvalidInterrupt(itype_val),
+                        self.index,
+                        self.instructions_retired,
+                    ));
+                };
+                let Some(target_sender) = self.senders.get(target_idx as usize) else {
+                    return Err(CpuError::new(
+                        self.program_counter,
+                        self.instruction_address,
+                        self.stack_pointer,
+                        self.registers,
+                        CpuErrorType::InvalidInterruptTarget(target_idx),
+                        self.index,
+                        self.instructions_retired,
+                    ));
+                };
+                let msg = Interrupt {
+                    sender_id: self.index,
+                    interrupt_type,
+                    payload: None,
+                };
+                if target_idx == self.index {
+                    info!("Handling {} sent to itself", msg);
+                    self.handle_interrupts(msg)?;
+                } else {
+                    info!("Sent {} to Core {}", msg, target_idx);
                     let _ = target_sender.send(msg);
                 }
             }
-            _ => {
-                return Err(CpuError::new(
-                    self.program_counter,
-                    self.stack_pointer,
-                    self.registers,
-                    CpuErrorType::UnimplementedOpCode(opcode),
-                    self.index,
-                ));
+            OpCode::IRPT_SEND_DATA => {
+                let target_idx = encoding::field_a(instruction);
+                let rs1 = encoding::field_b(instruction);
+                let Some(target_sender) = self.senders.get(target_idx as usize) else {
+                    return Err(CpuError::new(
+                        self.program_counter,
+                        self.instruction_address,
+                        self.stack_pointer,
+                        self.registers,
+                        CpuErrorType::InvalidInterruptTarget(target_idx),
+                        self.index,
+                        self.instructions_retired,
+                    ));
+                };
+                let msg = Interrupt {
+                    sender_id: self.index,
+                    interrupt_type: InterruptType::Data,
+                    payload: Some(self.registers[rs1 as usize]),
+                };
+                if target_idx == self.index {
+                    info!("Handling {} sent to itself", msg);
+                    self.handle_interrupts(msg)?;
+                } else {
+                    info!("Sent {} with payload {} to Core {}", msg, self.registers[rs1 as usize], target_idx);
+                    let _ = target_sender.send(msg);
+                }
             }
         }
-        std::thread::sleep(std::time::Duration::from_millis(10));
+        self.instructions_retired += 1;
+        let total = self.total_retired.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+        if self.max_cycles.is_some_and(|limit| total >= limit) {
+            info!("Reached the limit of {} instructions, stopping VM...", total);
+            self.running.store(false, std::sync::atomic::Ordering::Relaxed);
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+impl Core {
+    /// Builds core 0 over a fresh bus without any devices, with `image` loaded at address 0 like a
+    /// ROM. The image has to start with the reset vector. The stack region is the one of the full
+    /// 32-bit address space starting at 0x4000_0000, of which only the first 0x1000 bytes are backed
+    /// by memory. Interrupts go to the core itself, so tests can call `tick` directly and inspect
+    /// the registers afterwards.
+    pub fn new_for_test(image: &[u8]) -> Core {
+        let bus = crate::mmio::Bus::new(crate::memory::Memory::from_bytes(image, 0x4000_1000).unwrap());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let senders = vec![sender.clone(); 4];
+        let mut core = Core::new(
+            0,
+            senders,
+            receiver,
+            std::sync::Arc::new(std::sync::RwLock::new(bus)),
+            std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true)),
+            0,
+        );
+        (core.stack_base, core.stack_size) = default_stack(0x1_0000_0000);
+        core.stack_pointer = core.stack_base;
+        core
+    }
+}
+
+/// Sign-extends the lowest `bits` bits of `value` to a full 32-bit word.
+pub fn sign_extend(value: u32, bits: u32) -> u32 {
+    let shift = 32 - bits;
+    (((value << shift) as i32) >> shift) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::encode;
+
+    /// Builds core 0 with its reset vector pointing at `program`, which is placed at 0x10.
+    fn core_with_program(program: &[u32]) -> Core {
+        let mut image = vec![0; 0x10];
+        image[0x0..0x4].copy_from_slice(&0x10u32.to_le_bytes());
+        image.extend(program.iter().flat_map(|word| word.to_le_bytes()));
+        Core::new_for_test(&image)
+    }
+
+    #[test]
+    fn beq_branches_after_equal_cmp() {
+        let mut core = core_with_program(&[
+            encode(OpCode::LOAD_IMM, (1 << 20) | 42),
+            encode(OpCode::LOAD_IMM, (2 << 20) | 42),
+            encode(OpCode::CMP, (1 << 20) | (2 << 15)),
+            encode(OpCode::BEQ_IMM, 0x100),
+        ]);
+        for _ in 0..4 {
+            core.tick().unwrap();
+        }
+        assert!(core.eq_flag);
+        assert_eq!(core.program_counter, 0x100);
+    }
+
+    #[test]
+    fn soft_reset_rejects_vectors_outside_memory() {
+        let mut core = core_with_program(&[encode(OpCode::RSET_SOFT, 0)]);
+        core.bus.read().unwrap().ram.write().unwrap().data[0x0..0x4].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        let error = core.tick().unwrap_err();
+        assert_eq!(error.error_type, CpuErrorType::InvalidResetVector(0xFFFF_FFF0));
+        assert_eq!(core.program_counter, 0x14);
+    }
+
+    #[test]
+    fn each_core_reads_its_own_index() {
+        let bus = crate::mmio::Bus::new_empty(0x4000_1000);
+        {
+            let mut memory = bus.ram.write().unwrap();
+            for i in 0..4 {
+                memory.data[i * 4..i * 4 + 4].copy_from_slice(&0x10u32.to_le_bytes());
+            }
+            memory.data[0x10..0x14].copy_from_slice(&encode(OpCode::COREID, 5 << 20).to_le_bytes());
+        }
+        let bus = std::sync::Arc::new(std::sync::RwLock::new(bus));
+        let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let (sender, _) = std::sync::mpsc::channel();
+        let senders = vec![sender.clone(); 4];
+        let handles: Vec<_> = (0..4)
+            .map(|index| {
+                let (_, receiver) = std::sync::mpsc::channel();
+                let mut core = Core::new(index, senders.clone(), receiver, bus.clone(), running.clone(), 0);
+                std::thread::spawn(move || {
+                    core.tick().unwrap();
+                    core.registers[5]
+                })
+            })
+            .collect();
+        let ids: Vec<u32> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert_eq!(ids, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn special_register_moves() {
+        let mut core = core_with_program(&[
+            encode(OpCode::RDPC, 1 << 20),
+            encode(OpCode::LDUP_IMM, (2 << 20) | 0x40001),
+            encode(OpCode::WRSP, 2 << 20),
+            encode(OpCode::RDSP, 3 << 20),
+            encode(OpCode::LDUP_IMM, (4 << 20) | 0x80001),
+            encode(OpCode::WRSP, 4 << 20),
+        ]);
+        for _ in 0..5 {
+            core.tick().unwrap();
+        }
+        assert_eq!(core.registers[1], 0x14);
+        assert_eq!(core.registers[3], 0x4000_1000);
+        assert_eq!(core.tick().unwrap_err().error_type, CpuErrorType::StackOpOutOfBounds);
+        assert_eq!(core.stack_pointer, 0x4000_1000);
+    }
+
+    #[test]
+    fn yield_sleeps_for_the_given_time() {
+        let mut core = core_with_program(&[encode(OpCode::YIELD, 0), encode(OpCode::YIELD, 20)]);
+        let start = std::time::Instant::now();
+        core.tick().unwrap();
+        core.tick().unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(20));
+        assert_eq!(core.program_counter, 0x18);
+    }
+
+    #[test]
+    fn long_yield_returns_once_the_vm_stops() {
+        let mut core = core_with_program(&[encode(OpCode::YIELD, 0x1FFFFFF)]);
+        let running = core.running.clone();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            running.store(false, std::sync::atomic::Ordering::Relaxed);
+        });
+        let start = std::time::Instant::now();
+        core.tick().unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn shutdown_stops_the_vm() {
+        let mut core = core_with_program(&[encode(OpCode::SHUTDOWN, 0)]);
+        core.tick().unwrap();
+        assert!(!core.running.load(std::sync::atomic::Ordering::Relaxed));
+        assert!(!core.halted);
+    }
+
+    #[test]
+    fn invalid_opcode_is_reported() {
+        let mut core = core_with_program(&[0x7F << 25, encode(OpCode::NOOP, 0)]);
+        assert_eq!(core.tick().unwrap_err().error_type, CpuErrorType::InvalidOpCode(0x7F));
+        assert_eq!(core.instructions_retired(), 0);
+        // Modes that ignore the error continue with the next instruction
+        core.tick().unwrap();
+        assert_eq!(core.program_counter, 0x18);
+    }
+
+    #[test]
+    fn register_jumps_reject_targets_outside_of_memory() {
+        let program = [
+            encode(OpCode::LOAD_IMM, (1 << 20) | 1),
+            encode(OpCode::LOAD_IMM, (2 << 20) | 31),
+            encode(OpCode::SHL, (3 << 20) | (1 << 15) | (2 << 10)),
+        ];
+        for jump in [
+            encode(OpCode::JUMP_REG, 3 << 20),
+            encode(OpCode::BRAN_REG, 3 << 20),
+            encode(OpCode::JUEQ_REG, 3 << 10),
+            encode(OpCode::BREQ_REG, 3 << 10),
+        ] {
+            let mut core = core_with_program(&[program[0], program[1], program[2], jump]);
+            for _ in 0..3 {
+                core.tick().unwrap();
+            }
+            let stack_pointer = core.stack_pointer;
+            assert_eq!(core.tick().unwrap_err().error_type, CpuErrorType::MemoryFault(0x8000_0000));
+            assert_eq!(core.program_counter, 0x20);
+            assert_eq!(core.stack_pointer, stack_pointer);
+        }
+    }
+
+    #[test]
+    fn fetch_outside_of_memory_is_a_fault() {
+        let mut core = core_with_program(&[]);
+        core.program_counter = 0x4000_0FFE;
+        let error = core.tick().unwrap_err();
+        assert_eq!(error.error_type, CpuErrorType::MemoryFault(0x4000_0FFE));
+        assert_eq!(error.instruction_address, 0x4000_0FFE);
+
+        core.program_counter = 0xFFFF_FFFC;
+        assert_eq!(core.tick().unwrap_err().error_type, CpuErrorType::MemoryFault(0xFFFF_FFFC));
+        assert_eq!(core.program_counter, 0);
+        assert_eq!(core.instructions_retired(), 0);
+    }
+
+    #[test]
+    fn fetch_at_the_top_of_a_full_address_space_wraps() {
+        let bus = crate::mmio::Bus::new_empty(0x1_0000_0000);
+        bus.ram.write().unwrap().data[0xFFFF_FFFC..].copy_from_slice(&encode(OpCode::NOOP, 0).to_le_bytes());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut core = Core::new(
+            0,
+            vec![sender],
+            receiver,
+            std::sync::Arc::new(std::sync::RwLock::new(bus)),
+            std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true)),
+            0,
+        );
+        core.program_counter = 0xFFFF_FFFC;
+        core.tick().unwrap();
+        assert_eq!(core.program_counter, 0);
+        assert_eq!(core.instructions_retired(), 1);
+    }
+
+    #[test]
+    fn interrupt_vector_table_follows_the_reset_vectors_of_all_cores() {
+        assert_eq!(interrupt_vector_table(1), 0x10);
+        assert_eq!(interrupt_vector_table(4), 0x10);
+        assert_eq!(interrupt_vector_table(8), 0x20);
+
+        let mut image = vec![0; 0x100];
+        image[0x0..0x4].copy_from_slice(&0x80u32.to_le_bytes());
+        image[0x24..0x28].copy_from_slice(&0xC0u32.to_le_bytes());
+        let bus = crate::mmio::Bus::new(crate::memory::Memory::from_bytes(&image, 0x4000_1000).unwrap());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut core = Core::new(
+            0,
+            vec![sender; 8],
+            receiver,
+            std::sync::Arc::new(std::sync::RwLock::new(bus)),
+            std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true)),
+            0,
+        );
+        core.busy = true;
+        core.handle_interrupts(Interrupt {
+            sender_id: crate::cpu::DEVICE_SENDER_ID,
+            interrupt_type: InterruptType::Irq(1),
+            payload: None,
+        }).unwrap();
+        core.enter_pending_irq().unwrap();
+        assert_eq!(core.program_counter, 0xC0);
+    }
+
+    #[test]
+    fn repeated_irqs_stay_pending_once() {
+        let mut core = core_with_program(&[encode(OpCode::NOOP, 0)]);
+        core.interrupts_enabled = false;
+        let mut gpu = crate::gpu::GPU::init(0x1000, 64, 64, Some(0), core.bus.read().unwrap().ram.clone());
+        gpu.write32(5, 1);
+        for _ in 0..60 {
+            gpu.send_vsync(&core.senders);
+        }
+        core.receive_interrupts().unwrap();
+        assert_eq!(core.pending_irqs, [crate::gpu::VSYNC_VECTOR]);
+    }
+
+    #[test]
+    fn huge_timer_vector_is_a_fault() {
+        let mut core = core_with_program(&[encode(OpCode::NOOP, 0)]);
+        let mut timer = crate::timer::Timer::new(core.senders.clone());
+        // IRQ to core 0 with a vector whose IVT entry doesn't fit into 32 bits
+        timer.write32(2, 0);
+        timer.write32(3, 5);
+        timer.write32(5, 0x4000_0000);
+        timer.write32(0, 1);
+        timer.write32(1, 1);
+        timer.tick();
+        core.busy = true;
+        core.receive_interrupts().unwrap();
+        assert_eq!(*core.enter_pending_irq().unwrap_err(), CpuErrorType::MemoryFault(0x4000_0000));
+        assert_eq!(core.stack_pointer, 0x4000_0000);
+    }
+
+    #[test]
+    fn counts_retired_instructions() {
+        let mut core = core_with_program(&[encode(OpCode::NOOP, 0); 5]);
+        for _ in 0..5 {
+            core.tick().unwrap();
+        }
+        assert_eq!(core.instructions_retired(), 5);
+    }
+
+    #[test]
+    fn halfword_round_trip() {
+        let mut core = core_with_program(&[
+            encode(OpCode::LOAD_IMM, (1 << 20) | 0x800),
+            encode(OpCode::LOAD_IMM, (2 << 20) | 0xBEEF),
+            encode(OpCode::STOR_HALF, (1 << 20) | (2 << 15)),
+            encode(OpCode::LOAD_HALF, (3 << 20) | (1 << 15)),
+            encode(OpCode::LOAD_HALF_S, (4 << 20) | (1 << 15)),
+        ]);
+        for _ in 0..5 {
+            core.tick().unwrap();
+        }
+        assert_eq!(core.registers[3], 0xBEEF);
+        assert_eq!(core.registers[4], 0xFFFF_BEEF);
+    }
+
+    #[test]
+    fn adc_adds_64_bit_values() {
+        // 0x00000001_FFFFFFFF + 0x00000002_00000001 = 0x00000004_00000000
+        let mut core = core_with_program(&[
+            encode(OpCode::LOAD_IMM, (1 << 20) | 0xFFFFF),
+            encode(OpCode::LDUP_IMM, (2 << 20) | 0xFFFFF),
+            encode(OpCode::ORR, (1 << 20) | (1 << 15) | (2 << 10)),
+            encode(OpCode::LOAD_IMM, (3 << 20) | 1),
+            encode(OpCode::LOAD_IMM, (4 << 20) | 1),
+            encode(OpCode::LOAD_IMM, (5 << 20) | 2),
+            encode(OpCode::CLC, 0),
+            encode(OpCode::ADC, (6 << 20) | (1 << 15) | (4 << 10)),
+            encode(OpCode::ADC, (7 << 20) | (3 << 15) | (5 << 10)),
+        ]);
+        core.carry_flag = true;
+        for _ in 0..8 {
+            core.tick().unwrap();
+        }
+        assert_eq!(core.registers[1], 0xFFFF_FFFF);
+        assert_eq!(core.registers[6], 0);
+        assert!(core.carry_flag);
+        assert!(!core.overflow_flag);
+        core.tick().unwrap();
+        assert_eq!(core.registers[7], 4);
+        assert!(!core.carry_flag);
+    }
+
+    #[test]
+    fn jump_rel_loops_backwards() {
+        let mut core = core_with_program(&[
+            encode(OpCode::NOOP, 0),
+            encode(OpCode::JUMP_REL, (-8i32 as u32) & 0x1FFFFFF),
+        ]);
+        core.tick().unwrap();
+        core.tick().unwrap();
+        assert_eq!(core.program_counter, 0x10);
+    }
+
+    #[test]
+    fn call_rel_returns_past_the_call() {
+        let mut core = core_with_program(&[
+            encode(OpCode::CALL_REL, 8),
+            encode(OpCode::LOAD_IMM, (1 << 20) | 1),
+            encode(OpCode::HALT, 0),
+            encode(OpCode::LOAD_IMM, (2 << 20) | 2),
+            encode(OpCode::RTRN_POP, 0),
+        ]);
+        core.tick().unwrap();
+        assert_eq!(core.program_counter, 0x1C);
+        assert_eq!(core.stack_pointer, 0x4000_0004);
+        core.tick().unwrap();
+        core.tick().unwrap();
+        assert_eq!(core.program_counter, 0x14);
+        assert_eq!(core.stack_pointer, 0x4000_0000);
+        core.tick().unwrap();
+        assert_eq!(core.registers[1..3], [1, 2]);
+    }
+
+    #[test]
+    fn load_imm_and_ldup_imm_compose_a_word() {
+        let mut core = core_with_program(&[
+            encode(OpCode::LOAD_IMM, (1 << 20) | 0x678),
+            encode(OpCode::LDUP_IMM, (1 << 20) | 0x12345),
+            encode(OpCode::LDUP_IMM, (1 << 20) | 0xFFFFF),
+        ]);
+        core.tick().unwrap();
+        core.tick().unwrap();
+        assert_eq!(core.registers[1], 0x12345678);
+        core.tick().unwrap();
+        assert_eq!(core.registers[1], 0xFFFFF678);
+    }
+
+    #[test]
+    fn cas_only_swaps_the_expected_value() {
+        let mut core = core_with_program(&[
+            encode(OpCode::LOAD_IMM, (1 << 20) | 0x800),
+            encode(OpCode::LOAD_IMM, (2 << 20) | 5),
+            encode(OpCode::CAS, (2 << 20) | (1 << 15) | (3 << 10)),
+            encode(OpCode::CAS, (2 << 20) | (1 << 15) | (3 << 10)),
+            encode(OpCode::LOAD_WORD, (4 << 20) | (1 << 15)),
+        ]);
+        for _ in 0..3 {
+            core.tick().unwrap();
+        }
+        assert!(core.eq_flag);
+        core.tick().unwrap();
+        assert!(!core.eq_flag);
+        core.tick().unwrap();
+        assert_eq!(core.registers[4], 5);
+    }
+
+    #[test]
+    fn cas_lets_exactly_one_core_take_a_lock() {
+        let bus = crate::mmio::Bus::new_empty(0x4000_1000);
+        {
+            let mut memory = bus.ram.write().unwrap();
+            for core in 0..4 {
+                memory.data[core * 4..core * 4 + 4].copy_from_slice(&0x10u32.to_le_bytes());
+            }
+            let program = [
+                encode(OpCode::LOAD_IMM, (1 << 20) | 0x800),
+                encode(OpCode::COREID, 2 << 20),
+                encode(OpCode::ADDI, (2 << 20) | 1),
+                encode(OpCode::CAS, (2 << 20) | (1 << 15) | (3 << 10)),
+            ];
+            for (i, word) in program.iter().enumerate() {
+                memory.data[0x10 + i * 4..0x14 + i * 4].copy_from_slice(&word.to_le_bytes());
+            }
+        }
+        let bus = std::sync::Arc::new(std::sync::RwLock::new(bus));
+        let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let (sender, _) = std::sync::mpsc::channel();
+        let senders = vec![sender.clone(); 4];
+        let handles: Vec<_> = (0..4)
+            .map(|index| {
+                let (_, receiver) = std::sync::mpsc::channel();
+                let mut core = Core::new(index, senders.clone(), receiver, bus.clone(), running.clone(), 0);
+                std::thread::spawn(move || {
+                    for _ in 0..4 {
+                        core.tick().unwrap();
+                    }
+                    core.eq_flag
+                })
+            })
+            .collect();
+        let taken: Vec<bool> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert_eq!(taken.iter().filter(|&&taken| taken).count(), 1);
+        let owner = bus.read().unwrap().read32(0x800);
+        assert!(taken[owner as usize - 1]);
+    }
+
+    #[test]
+    fn btst_reads_a_single_bit() {
+        let mut core = core_with_program(&[
+            encode(OpCode::LOAD_IMM, (1 << 20) | 0x80),
+            encode(OpCode::BTST, (1 << 20) | (7 << 15)),
+            encode(OpCode::BTST, (1 << 20) | (6 << 15)),
+            encode(OpCode::BTST, (1 << 20) | (7 << 15)),
+            encode(OpCode::BNE_IMM, 0x100),
+        ]);
+        core.tick().unwrap();
+        core.tick().unwrap();
+        assert!(core.eq_flag);
+        core.tick().unwrap();
+        assert!(!core.eq_flag);
+        core.tick().unwrap();
+        core.tick().unwrap();
+        assert_eq!(core.program_counter, 0x24);
+    }
+
+    #[test]
+    fn load_full_skips_its_constant() {
+        let mut core = core_with_program(&[
+            encode(OpCode::LOAD_FULL, 1 << 20),
+            0xDEADBEEF,
+            encode(OpCode::LOAD_IMM, (2 << 20) | 7),
+        ]);
+        core.tick().unwrap();
+        assert_eq!(core.registers[1], 0xDEADBEEF);
+        assert_eq!(core.program_counter, 0x18);
+        core.tick().unwrap();
+        assert_eq!(core.registers[2], 7);
+    }
+
+    #[test]
+    fn stack_round_trip_preserves_value() {
+        let mut core = core_with_program(&[]);
+        core.write_u32_to_ram(0xDEADBEEF).unwrap();
+        assert_eq!(core.pop_u32_from_ram().unwrap(), 0xDEADBEEF);
+        assert_eq!(core.stack_pointer, 0x4000_0000);
+    }
+
+    #[test]
+    fn stack_stays_within_configured_region() {
+        let mut core = core_with_program(&[]);
+        core.stack_base = 0x2000;
+        core.stack_size = 8;
+        core.stack_pointer = 0x2000;
+        core.write_u32_to_ram(1).unwrap();
+        core.write_u32_to_ram(2).unwrap();
+        assert_eq!(core.write_u32_to_ram(3).unwrap_err().error_type, CpuErrorType::StackOverflow);
+        assert_eq!(core.pop_u32_from_ram().unwrap(), 2);
+        assert_eq!(core.pop_u32_from_ram().unwrap(), 1);
+        assert_eq!(core.pop_u32_from_ram().unwrap_err().error_type, CpuErrorType::StackOpOutOfBounds);
+    }
+
+    #[test]
+    fn push_pop_round_trip() {
+        let mut core = core_with_program(&[
+            encode(OpCode::LOAD_IMM, (1 << 20) | 0x12345),
+            encode(OpCode::PUSH, 1 << 20),
+            encode(OpCode::POP, 2 << 20),
+            encode(OpCode::POP, 3 << 20),
+        ]);
+        for _ in 0..3 {
+            core.tick().unwrap();
+        }
+        assert_eq!(core.registers[2], 0x12345);
+        assert_eq!(core.stack_pointer, 0x4000_0000);
+        assert_eq!(*core.tick().unwrap_err(), CpuErrorType::StackOpOutOfBounds);
+    }
+
+    #[test]
+    fn halted_core_waits_for_resume() {
+        let core = core_with_program(&[]);
+        let sender = core.senders[0].clone();
+        sender.send(Interrupt { sender_id: 1, interrupt_type: InterruptType::Halt, payload: None }).unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let mut core = core;
+            core.poll_interrupts().unwrap();
+            core
+        });
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(!handle.is_finished());
+
+        sender.send(Interrupt { sender_id: 1, interrupt_type: InterruptType::Resume, payload: None }).unwrap();
+        let core = handle.join().unwrap();
+        assert!(!core.halted);
+        assert_eq!(core.program_counter, 0x10);
+    }
+
+    #[test]
+    fn halt_stops_a_busy_core_until_resumed() {
+        let mut core = core_with_program(&[encode(OpCode::NOOP, 0), encode(OpCode::HALT, 0), encode(OpCode::NOOP, 0)]);
+        core.busy = true;
+        core.tick().unwrap();
+        assert_eq!(core.tick().unwrap_err().error_type, CpuErrorType::Halt);
+        assert!(core.halted);
+        let sender = core.senders[0].clone();
+
+        let handle = std::thread::spawn(move || {
+            let mut core = core;
+            core.poll_interrupts().unwrap();
+            core
+        });
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(!handle.is_finished());
+
+        sender.send(Interrupt { sender_id: 1, interrupt_type: InterruptType::Resume, payload: None }).unwrap();
+        let mut core = handle.join().unwrap();
+        // Resumed where it halted instead of at the reset vector
+        assert!(!core.halted);
+        assert_eq!(core.program_counter, 0x18);
+        assert_eq!(core.instructions_retired(), 1);
+        core.tick().unwrap();
+        assert_eq!(core.program_counter, 0x1C);
+    }
+
+    #[test]
+    fn irpt_send_data_lands_in_r31_of_the_target() {
+        let mut core = core_with_program(&[
+            encode(OpCode::LOAD_IMM, (2 << 20) | 0xBEEF),
+            encode(OpCode::IRPT_SEND_DATA, (1 << 20) | (2 << 15)),
+            encode(OpCode::IRPT_SEND_DATA, (4 << 20) | (2 << 15)),
+        ]);
+        let mut target = core_with_program(&[]);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        core.senders[1] = sender;
+        target.receiver = receiver;
+
+        core.tick().unwrap();
+        core.tick().unwrap();
+        target.receive_interrupts().unwrap();
+        assert_eq!(target.registers[31], 0xBEEF);
+        assert_eq!(core.registers[31], 0);
+
+        assert_eq!(core.tick().unwrap_err().error_type, CpuErrorType::InvalidInterruptTarget(4));
+    }
+
+    #[test]
+    fn self_soft_reset_is_handled_inline() {
+        let mut core = core_with_program(&[
+            encode(OpCode::NOOP, 0),
+            encode(OpCode::IRPT_SEND, 3 << 15), // SoftReset to core 0
+        ]);
+        core.tick().unwrap();
+        core.tick().unwrap();
+        assert_eq!(core.program_counter, 0x10);
+        assert!(core.receiver.try_recv().is_err());
+    }
+}
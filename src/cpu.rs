@@ -1,12 +1,11 @@
-use std::io::Read;
-
 use crate::opcodes::OpCode;
 
-/// A 32-bit 4-Core CPU
+/// A 32-bit multi-core CPU, with 4 cores unless configured otherwise
 ///
 /// # ==== General ====
 ///
-/// - 32 General Purpose Registers
+/// - 32 General Purpose Registers. None of them is hardwired to zero, use `CLR` to zero a register
+///   in a single instruction.
 ///
 /// # ==== Instructions ====
 /// Instructions are 32-bit words read from memory in 4x8-bit steps. An instruction can be
@@ -17,58 +16,126 @@ use crate::opcodes::OpCode;
 pub struct CPU {
     pub mode: CpuMode,
     pub memory: std::sync::Arc<std::sync::RwLock<crate::mmio::Bus>>,
-    pub cores: [Option<crate::core::Core>; 4],
+    pub cores: Vec<Option<crate::core::Core>>,
+    /// Interrupt senders for every core, indexed by core index. Devices that raise interrupts get a
+    /// clone of these.
+    pub senders: Vec<std::sync::mpsc::Sender<Interrupt>>,
     pub channel: (
         std::sync::mpsc::Sender<CpuError>,
         std::sync::mpsc::Receiver<CpuError>,
     ),
+    pub running: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    /// RAM range written to disk when an error occurs in Debug mode. Defaults to the default stack
+    /// region, see `core::default_stack`.
+    pub dump_range: std::ops::Range<u32>,
+    /// Stop the VM once no core is executing anymore, i.e. every core is halted or idle.
+    pub exit_when_idle: bool,
 }
 
 impl CPU {
     pub fn new(
         mode: CpuMode,
         memory: std::sync::Arc<std::sync::RwLock<crate::mmio::Bus>>,
-        running: std::sync::Arc<std::sync::atomic::AtomicBool>
-    ) -> Self {
-        let mut tx_rx_pairs: Vec<_> = (0..4).map(|_| std::sync::mpsc::channel()).collect();
-
-        let all_senders: [std::sync::mpsc::Sender<Interrupt>; 4] = [
-            tx_rx_pairs[0].0.clone(),
-            tx_rx_pairs[1].0.clone(),
-            tx_rx_pairs[2].0.clone(),
-            tx_rx_pairs[3].0.clone(),
-        ];
-
-        let cores = std::array::from_fn(|i| {
-            let (_own_tx, own_rx) = tx_rx_pairs.remove(0);
-            let mut core = crate::core::Core::new(i as u32, all_senders.clone(), own_rx, memory.clone(), running.clone());
+        running: std::sync::Arc<std::sync::atomic::AtomicBool>,
+        clock_hz: u32,
+        arith: ArithMode,
+        core_count: usize
+    ) -> Result<Self, CoreCountError> {
+        if !(1..=crate::core::MAX_CORES as usize).contains(&core_count) {
+            return Err(CoreCountError::OutOfRange { cores: core_count });
+        }
+        let (all_senders, receivers): (Vec<_>, Vec<_>) = (0..core_count).map(|_| std::sync::mpsc::channel()).unzip();
+
+        let memory_size = memory.read().unwrap().ram.read().unwrap().data.len();
+        let (stack_base, stack_size) = crate::core::default_stack(memory_size);
+        let total_retired = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let cores = receivers.into_iter().enumerate().map(|(i, own_rx)| {
+            let mut core = crate::core::Core::new(i as u32, all_senders.clone(), own_rx, memory.clone(), running.clone(), clock_hz);
+            core.step = matches!(mode, CpuMode::Step);
+            core.arith = arith;
+            core.total_retired = total_retired.clone();
             if i == 0 {
                 core.busy = true;
                 info!("Assigned busy to core {}", i)
             }
             Some(core)
-        });
+        }).collect();
 
-        Self {
+        Ok(Self {
             mode,
             memory: memory,
             cores,
+            senders: all_senders,
             channel: std::sync::mpsc::channel::<CpuError>(),
+            running,
+            dump_range: stack_base..stack_base + stack_size,
+            exit_when_idle: false,
+        })
+    }
+
+    /// Executes a single instruction on core `core_index` on the calling thread, after handling the
+    /// interrupts already sent to it. Doesn't wait for the clock or for interrupts, so a halted or
+    /// idle core does nothing. Errors are returned to the caller instead of being handled according
+    /// to `mode`, including `CoreUnavailable` if the core doesn't exist or is currently running on
+    /// its own thread.
+    pub fn step(&mut self, core_index: usize) -> Result<(), CpuError> {
+        let Some(core) = self.cores.get_mut(core_index).and_then(Option::as_mut) else {
+            return Err(CpuError::new(
+                0,
+                0,
+                0,
+                [0; 32],
+                CpuErrorType::CoreUnavailable(core_index as u32),
+                core_index as u32,
+                0,
+            ));
+        };
+        core.receive_interrupts()?;
+        if core.halted || !core.busy {
+            return Ok(());
         }
+        core.enter_pending_irq()?;
+        core.tick()
     }
 
-    fn handle_errors(&mut self, error: CpuError) {
+    /// Stops the VM once all cores together executed `limit` instructions.
+    pub fn set_max_cycles(&mut self, limit: u64) {
+        for core in self.cores.iter_mut().flatten() {
+            core.max_cycles = Some(limit);
+        }
+    }
+
+    /// Moves the stack region of every core to `size` bytes starting at `base` and resets their
+    /// stack pointers to it. Fails if the region does not fit into memory.
+    pub fn set_stack(&mut self, base: u32, size: u32) -> Result<(), StackError> {
+        let memory_size = self.memory.read().unwrap().ram.read().unwrap().data.len();
+        if base as usize + size as usize > memory_size {
+            return Err(StackError::OutOfBounds { base, size, memory_size });
+        }
+        for core in self.cores.iter_mut().flatten() {
+            core.stack_base = base;
+            core.stack_size = size;
+            core.stack_pointer = base;
+        }
+        Ok(())
+    }
+
+    /// Logs `error` and decides whether the VM can keep running in the current mode. Errors that
+    /// stop the VM are handed back.
+    fn handle_errors(&mut self, error: CpuError) -> Result<(), CpuError> {
         let severity = error.severity();
+        if matches!(severity, CpuErrorSeverity::Info) {
+            info!(core = error.core_index, "Core {} stopped: {}", error.core_index, error.error_type);
+            return Ok(());
+        }
         info!(?severity, "Handling error: {}", error);
         match self.mode {
             CpuMode::Safe => {
-                info!("Shutting down VM...");
-                std::process::exit(1);
+                return Err(error);
             }
             CpuMode::Stable => {
                 if matches!(severity, CpuErrorSeverity::Severe) {
-                    info!("Shutting down VM...");
-                    std::process::exit(1);
+                    return Err(error);
                 } else {
                     info!("Ignoring error...");
                 }
@@ -76,87 +143,232 @@ impl CPU {
             CpuMode::Unstable => {
                 info!("Ignoring error...");
             }
-            CpuMode::Debug => {
+            CpuMode::Debug | CpuMode::Step => {
+                self.dump(&error);
                 info!(
-                    core=?error.core_index,
+                    core = error.core_index,
                     "\nProgram Counter: 0x{:08X}\nStack Pointer: 0x{:08X}\nRegisters: {:?}\n",
                     error.program_counter,
                     error.stack_pointer,
                     error.register_snapshot
                 );
-                info!(core=?error.core_index, "Press ENTER to let this core continue running");
-                loop {
-                    let mut input = [0u8; 1];
-                    std::io::stdin().read_exact(&mut input).unwrap();
-                    if input[0] == b'\n' {
-                        break;
-                    }
-                }
             }
         }
+        Ok(())
+    }
+
+    /// Writes `dump_range` of RAM, the register snapshot of `error` and a listing of the code at the
+    /// faulting instruction to the current directory. The text file is written even if the RAM
+    /// dump fails.
+    fn dump(&self, error: &CpuError) {
+        let name = format!("core{}_0x{:08X}", error.core_index, error.instruction_address);
+        let bus = self.memory.read().unwrap();
+        let memory = match bus.ram.read().unwrap().dump(self.dump_range.clone(), &format!("{name}.bin")) {
+            Ok(()) => format!("0x{:08X} - 0x{:08X} in {}.bin", self.dump_range.start, self.dump_range.end, name),
+            Err(e) => {
+                error!("Could not write memory dump: {}", e);
+                format!("0x{:08X} - 0x{:08X} not dumped: {}", self.dump_range.start, self.dump_range.end, e)
+            }
+        };
+        let mut registers = String::new();
+        for (i, value) in error.register_snapshot.iter().enumerate() {
+            registers.push_str(&format!("r{i}: 0x{value:08X}\n"));
+        }
+        // The instructions from the faulting one on
+        let ram = bus.ram.read().unwrap();
+        let words: Vec<u32> = (0..8u32)
+            .map(|i| error.instruction_address as usize + i as usize * 4)
+            .map_while(|addr| ram.data.get(addr..addr + 4))
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        let mut code = String::new();
+        for (address, line) in crate::disasm::listing(&words, error.instruction_address) {
+            code.push_str(&format!("0x{address:08X}: {line}\n"));
+        }
+        let report = format!(
+            "{}\nProgram Counter: 0x{:08X}\nStack Pointer: 0x{:08X}\nMemory: {}\n{}\n{}",
+            error,
+            error.program_counter,
+            error.stack_pointer,
+            memory,
+            registers,
+            code
+        );
+        match std::fs::write(format!("{name}.txt"), report) {
+            Ok(()) => info!("Dumped CPU data to {}.txt", name),
+            Err(e) => error!("Could not write register dump: {}", e),
+        }
     }
 
-    pub fn run(&mut self) {
+    /// Runs every core on its own thread until the VM stops. Returns the error that stopped it, if
+    /// the current mode doesn't tolerate it.
+    pub fn run(&mut self) -> Result<(), CpuError> {
         let mut handles = Vec::new();
 
+        // Whether each core is currently halted or idle, published by the core threads
+        let idle: std::sync::Arc<Vec<std::sync::atomic::AtomicBool>> = std::sync::Arc::new(self.cores.iter().map(|core| {
+            let core = core.as_ref().unwrap();
+            std::sync::atomic::AtomicBool::new(core.halted || !core.busy)
+        }).collect());
+
         for core in self.cores.iter_mut() {
             let mut core = core.take().unwrap();
             let cpu_mode = self.mode.clone();
             let tx = self.channel.0.clone();
+            let idle = idle.clone();
 
             let handle = std::thread::Builder::new()
                 .name(format!("RustyVM-Core-{}", core.index))
                 .spawn(move || {
+                    // Tags every event of this core, including the ones logged by `Core` itself
+                    let _span = info_span!("core", core = core.index).entered();
                     info!("Spawned thread: {}", std::thread::current().name().unwrap());
                     while core.running.load(std::sync::atomic::Ordering::Relaxed) {
-                        if let Ok(interrupt) = core.receiver.try_recv() {
-                            core.handle_interrupts(interrupt);
-                        }
-
-                        if !core.busy {
-                            if !core.running.load(std::sync::atomic::Ordering::Relaxed) {
-                                std::process::exit(0);
+                        // Published before polling as well, since polling blocks while the core is idle
+                        idle[core.index as usize].store(core.halted || !core.busy, std::sync::atomic::Ordering::Relaxed);
+                        let polled = core.poll_interrupts();
+                        idle[core.index as usize].store(core.halted || !core.busy, std::sync::atomic::Ordering::Relaxed);
+                        let result = match polled {
+                            Ok(()) if core.halted || !core.busy => continue,
+                            Ok(()) => {
+                                let result = core.tick();
+                                if result.is_ok() {
+                                    core.wait_for_next_cycle();
+                                }
+                                result
                             }
-                            if let Ok(interrupt) = core.receiver.try_recv() {
-                                core.handle_interrupts(interrupt);
-                            }
-                            continue;
-                        }
-
-                        let result = core.tick();
+                            Err(e) => Err(e),
+                        };
 
                         if let Err(e) = result {
-                            error!(core = core.index, "Core {} error: {}", core.index, e);
+                            if matches!(e.severity(), CpuErrorSeverity::Info) {
+                                info!("Core {}: {}", core.index, e.error_type);
+                                tx.send(e).unwrap();
+                                continue;
+                            }
+                            error!("Core {} error: {}", core.index, e);
+                            let instruction_address = e.instruction_address;
                             tx.send(e).unwrap();
                             match cpu_mode {
-                                CpuMode::Debug => loop {
-                                    let mut input = [0u8; 1];
-                                    std::io::stdin().read_exact(&mut input).unwrap();
-                                    if input[0] == b'\n' {
-                                        break;
-                                    }
-                                },
+                                CpuMode::Debug | CpuMode::Step => pause_after_error(&mut core, instruction_address),
                                 _ => {}
                             }
                         }
                     }
+                    core
                 })
                 .unwrap();
 
             handles.push(handle);
         }
 
-        loop {
-            match self.channel.1.recv() {
+        let mut result = Ok(());
+        while self.running.load(std::sync::atomic::Ordering::Relaxed) {
+            match self.channel.1.recv_timeout(std::time::Duration::from_millis(100)) {
                 Ok(error) => {
-                    self.handle_errors(error);
+                    if let Err(error) = self.handle_errors(error) {
+                        info!("Shutting down VM...");
+                        self.running.store(false, std::sync::atomic::Ordering::Relaxed);
+                        result = Err(error);
+                    }
+                }
+                // Only checked after a quiet period so a core that was just resumed has had time to
+                // report itself as busy again
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                    if self.exit_when_idle && idle.iter().all(|core| core.load(std::sync::atomic::Ordering::Relaxed)) {
+                        info!("All cores are halted or idle, stopping VM...");
+                        self.running.store(false, std::sync::atomic::Ordering::Relaxed);
+                    }
                 }
-                Err(_) => break,
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
             }
         }
+
+        // Hand the cores back so their final state can be inspected
+        for (slot, handle) in self.cores.iter_mut().zip(handles) {
+            *slot = Some(handle.join().unwrap());
+        }
+        result
     }
 }
 
+/// Guards stdin so cores waiting for ENTER at the same time don't interleave their prompts
+static STDIN_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Runs `prompt` and then reads a line from stdin, while holding the stdin lock.
+pub fn read_line(prompt: impl FnOnce()) -> String {
+    let _guard = STDIN_LOCK.lock().unwrap();
+    prompt();
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).unwrap();
+    line
+}
+
+/// Runs `prompt` and then blocks until ENTER is pressed, while holding the stdin lock.
+pub fn wait_for_enter(prompt: impl FnOnce()) {
+    read_line(prompt);
+}
+
+/// Pauses a core that ran into an error until the user either lets it continue after the faulting
+/// instruction or retries the instruction at `instruction_address`. Before that, registers and memory
+/// can be modified with `set rN VALUE` and `poke ADDRESS BYTE`.
+fn pause_after_error(core: &mut crate::core::Core, instruction_address: u32) {
+    use crate::mmio::AddressSpace;
+
+    loop {
+        let line = read_line(|| {
+            info!("Press ENTER or enter c to continue, r to retry the faulting instruction, set rN VALUE or poke ADDRESS BYTE to modify state");
+        });
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] | ["c"] => break,
+            ["r"] => {
+                info!("Retrying instruction at 0x{:08X}", instruction_address);
+                core.program_counter = instruction_address;
+                break;
+            }
+            ["set", register, value] => {
+                let index = register.strip_prefix('r').and_then(|index| index.parse::<usize>().ok());
+                match (index.filter(|&index| index < 32), parse_number(value)) {
+                    (Some(index), Some(value)) => {
+                        core.registers[index] = value;
+                        info!("r{} = 0x{:08X}", index, core.registers[index]);
+                    }
+                    _ => error!("Usage: set rN VALUE"),
+                }
+            }
+            ["poke", address, value] => match (parse_number(address), parse_number(value)) {
+                (Some(address), Some(value)) if value <= 0xFF && (address as usize) < core.memory_size => {
+                    let mut bus = core.bus.write().unwrap();
+                    bus.write8(address, value as u8);
+                    info!("0x{:08X} = 0x{:02X}", address, bus.read8(address));
+                }
+                _ => error!("Usage: poke ADDRESS BYTE"),
+            },
+            _ => error!("Unknown command: {}", line.trim()),
+        }
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number. Shared by the debugger prompt and the
+/// address arguments of the binary.
+pub fn parse_number(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Determines how `ADD`, `SUB`, `ADDI` and `SUBI` treat results that don't fit into 32 bits
+#[derive(Debug, Display, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ArithMode {
+    /// Results wrap around silently. `AddWithOverflow` and `SubWithOverflow` are never produced.
+    Wrapping,
+    /// The wrapped result of an addition is stored and `AddWithOverflow` is reported, a subtraction
+    /// that would underflow leaves the destination unchanged and reports `SubWithOverflow`.
+    Checked,
+}
+
 #[derive(Debug, Display, Clone)]
 /// Determines how the VM handles runtime Errors
 pub enum CpuMode {
@@ -168,33 +380,43 @@ pub enum CpuMode {
     Unstable,
     /// Dumps CPU and RAM data to the current directory on any runtime error and halts the VM.
     Debug,
+    /// Like `Debug`, but additionally waits for ENTER before every instruction.
+    Step,
 }
 
 #[derive(Debug, Display, Error, Deref)]
-#[display("{} {} {}: {}", self.severity(), format!("CPU error occured in Core:{} at", core_index), format!("0x{:08X}", program_counter - 4), error_type)]
+#[display("{} {} {}: {}", self.severity(), format!("CPU error occured in Core:{} after {} instructions at", core_index, instructions_retired), format!("0x{:08X}", instruction_address), error_type)]
 pub struct CpuError {
     #[deref]
     pub error_type: CpuErrorType,
     pub program_counter: u32,
+    /// Address of the instruction that caused the error. Execution resumes here on retry.
+    pub instruction_address: u32,
     pub stack_pointer: u32,
-    pub register_snapshot: [u32; 32],
+    /// Boxed to keep `Result<_, CpuError>` small, it is returned from every instruction.
+    pub register_snapshot: Box<[u32; 32]>,
     pub core_index: u32,
+    pub instructions_retired: u64,
 }
 
 impl CpuError {
     pub fn new(
         program_counter: u32,
+        instruction_address: u32,
         stack_pointer: u32,
         register_snapshot: [u32; 32],
         error_type: CpuErrorType,
         core_index: u32,
+        instructions_retired: u64,
     ) -> Self {
         Self {
             error_type,
             program_counter,
+            instruction_address,
             stack_pointer,
-            register_snapshot,
+            register_snapshot: Box::new(register_snapshot),
             core_index,
+            instructions_retired,
         }
     }
 }
@@ -203,6 +425,20 @@ impl CpuError {
 pub enum CpuErrorSeverity {
     Severe,
     Minor,
+    /// Not an error but a normal event, like a core reaching `HALT`. Never stops the VM.
+    Info,
+}
+
+#[derive(Debug, Display, Error, PartialEq)]
+pub enum CoreCountError {
+    #[display("{} cores requested, but only 1 - {} are supported", cores, crate::core::MAX_CORES)]
+    OutOfRange { cores: usize },
+}
+
+#[derive(Debug, Display, Error, PartialEq)]
+pub enum StackError {
+    #[display("Stack region 0x{:X} + 0x{:X} exceeds the {} byte memory", base, size, memory_size)]
+    OutOfBounds { base: u32, size: u32, memory_size: usize },
 }
 
 #[derive(Debug, Display, PartialEq)]
@@ -219,6 +455,17 @@ pub enum CpuErrorType {
     StackOpOutOfBounds,
     AddWithOverflow,
     SubWithOverflow,
+    MulWithOverflow,
+    #[display("Invalid interrupt type: {}", _0)]
+    InvalidInterrupt(u32),
+    #[display("Invalid interrupt target: Core {}", _0)]
+    InvalidInterruptTarget(u32),
+    #[display("Memory fault at 0x{:08X}", _0)]
+    MemoryFault(u32),
+    #[display("Reset vector 0x{:08X} points outside of the memory", _0)]
+    InvalidResetVector(u32),
+    #[display("Core {} does not exist or is running on its own thread", _0)]
+    CoreUnavailable(u32),
 }
 
 pub trait Severity {
@@ -232,20 +479,31 @@ impl Severity for CpuErrorType {
             CpuErrorType::InvalidInstruction(_) => CpuErrorSeverity::Severe,
             CpuErrorType::UnimplementedOpCode(_) => CpuErrorSeverity::Severe,
             CpuErrorType::InvalidOpCode(_) => CpuErrorSeverity::Severe,
-            CpuErrorType::Halt => CpuErrorSeverity::Severe,
+            CpuErrorType::Halt => CpuErrorSeverity::Info,
             CpuErrorType::DivisionByZero => CpuErrorSeverity::Minor,
             CpuErrorType::StackOpOutOfBounds => CpuErrorSeverity::Minor,
             CpuErrorType::AddWithOverflow => CpuErrorSeverity::Minor,
             CpuErrorType::SubWithOverflow => CpuErrorSeverity::Minor,
+            CpuErrorType::MulWithOverflow => CpuErrorSeverity::Minor,
+            CpuErrorType::InvalidInterrupt(_) => CpuErrorSeverity::Minor,
+            CpuErrorType::InvalidInterruptTarget(_) => CpuErrorSeverity::Minor,
+            CpuErrorType::MemoryFault(_) => CpuErrorSeverity::Severe,
+            CpuErrorType::InvalidResetVector(_) => CpuErrorSeverity::Severe,
+            CpuErrorType::CoreUnavailable(_) => CpuErrorSeverity::Severe,
         }
     }
 }
 
+/// Sender id used for interrupts raised by devices rather than by a core.
+pub const DEVICE_SENDER_ID: u32 = u32::MAX;
+
 #[derive(Debug, Display)]
 #[display("Interrupt {:?}", interrupt_type)]
 pub struct Interrupt {
     pub sender_id: u32,
     pub interrupt_type: InterruptType,
+    /// Value carried by a `Data` interrupt.
+    pub payload: Option<u32>,
 }
 
 #[derive(Debug, Display)]
@@ -254,4 +512,24 @@ pub enum InterruptType {
     Halt,
     SoftReset,
     HardReset,
+    /// Maskable hardware interrupt carrying the number of its interrupt vector.
+    #[display("Irq({_0})")]
+    Irq(u32),
+    /// Message from another core. The payload is stored in the receiver's r31.
+    Data,
+}
+
+impl InterruptType {
+    /// Decodes an interrupt type specifier as used by `IRPT_SEND`. `vector` is only used by
+    /// `Irq` (5).
+    pub fn from_code(code: u32, vector: u32) -> Option<Self> {
+        match code {
+            1 => Some(InterruptType::Resume),
+            2 => Some(InterruptType::Halt),
+            3 => Some(InterruptType::SoftReset),
+            4 => Some(InterruptType::HardReset),
+            5 => Some(InterruptType::Irq(vector)),
+            _ => None,
+        }
+    }
 }
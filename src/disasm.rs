@@ -0,0 +1,160 @@
+use crate::encoding;
+use crate::opcodes::OpCode;
+
+/// Turns a single instruction word into a human-readable mnemonic with its operands, e.g.
+/// `LOAD_IMM r3, 0x1`. Words that don't start with a valid opcode are shown as raw data.
+pub fn disassemble(instruction: u32) -> String {
+    let opcode = match OpCode::decode(instruction) {
+        Ok(opcode) => opcode,
+        Err(_) => return format!(".word 0x{:08X}", instruction),
+    };
+    let r1 = encoding::field_a(instruction);
+    let r2 = encoding::field_b(instruction);
+    let r3 = encoding::field_c(instruction);
+    let imm20 = encoding::imm20(instruction);
+    let imm25 = encoding::imm25(instruction);
+
+    let operands = match opcode {
+        OpCode::NOOP
+        | OpCode::RTRN
+        | OpCode::RTRN_POP
+        | OpCode::RSET_SOFT
+        | OpCode::RSET_HARD
+        | OpCode::CLI
+        | OpCode::SEI
+        | OpCode::CLC
+        | OpCode::SHUTDOWN
+        | OpCode::HALT => String::new(),
+        OpCode::LOAD_IMM
+        | OpCode::LDUP_IMM
+        | OpCode::STOR_IMM
+        | OpCode::ORI
+        | OpCode::ADDI
+        | OpCode::SUBI => format!("r{}, 0x{:X}", r1, imm20),
+        OpCode::JUMP_REG
+        | OpCode::BRAN_REG
+        | OpCode::PUSH
+        | OpCode::CLR
+        | OpCode::POP
+        | OpCode::COREID
+        | OpCode::RDSP
+        | OpCode::WRSP
+        | OpCode::RDPC
+        | OpCode::LOAD_FULL => {
+            format!("r{}", r1)
+        }
+        OpCode::LOAD_BYTE
+        | OpCode::LOAD_WORD
+        | OpCode::LOAD_HALF
+        | OpCode::LOAD_HALF_S
+        | OpCode::STOR_BYTE
+        | OpCode::STOR_HALF
+        | OpCode::MOV
+        | OpCode::NOT
+        | OpCode::CMP
+        | OpCode::CMP_LT
+        | OpCode::CMP_LTU => format!("r{}, r{}", r1, r2),
+        OpCode::ADD
+        | OpCode::SUB
+        | OpCode::ADC
+        | OpCode::SBC
+        | OpCode::MUL
+        | OpCode::DIV
+        | OpCode::MOD
+        | OpCode::AND
+        | OpCode::ORR
+        | OpCode::XOR
+        | OpCode::NAND
+        | OpCode::NOR
+        | OpCode::SHL
+        | OpCode::SHR
+        | OpCode::SAR
+        | OpCode::CAS
+        | OpCode::JUEQ_REG
+        | OpCode::BREQ_REG => format!("r{}, r{}, r{}", r1, r2, r3),
+        OpCode::JUMP_IMM
+        | OpCode::BRAN_IMM
+        | OpCode::BEQ_IMM
+        | OpCode::BNE_IMM
+        | OpCode::BLT_IMM
+        | OpCode::BGE_IMM => format!("0x{:08X}", imm25),
+        OpCode::YIELD => format!("{}", imm25),
+        OpCode::JUMP_REL | OpCode::CALL_REL => {
+            format_offset(crate::core::sign_extend(imm25, 25) as i32 as i64)
+        }
+        OpCode::IRPT_SEND => format!("core {}, type {}, vector {}", r1, r2, r3),
+        OpCode::BTST => format!("r{}, {}", r1, r2),
+        OpCode::IRPT_SEND_DATA => format!("core {}, r{}", r1, r2),
+    };
+
+    if operands.is_empty() {
+        format!("{}", opcode)
+    } else {
+        format!("{} {}", opcode, operands)
+    }
+}
+
+/// Disassembles the instructions in `words`, the first of which is at `address`, into one line per
+/// instruction with its address. Instructions longer than a word take the following words along,
+/// so the constant of `LOAD_FULL` is shown as its operand instead of as an instruction.
+pub fn listing(words: &[u32], address: u32) -> Vec<(u32, String)> {
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < words.len() {
+        let line_address = address.wrapping_add(i as u32 * 4);
+        let mut line = disassemble(words[i]);
+        match OpCode::decode(words[i]) {
+            Ok(OpCode::LOAD_FULL) if i + 1 < words.len() => {
+                line.push_str(&format!(", 0x{:08X}", words[i + 1]));
+                i += OpCode::LOAD_FULL.length() as usize / 4;
+            }
+            _ => i += 1,
+        }
+        lines.push((line_address, line));
+    }
+    lines
+}
+
+fn format_offset(offset: i64) -> String {
+    if offset < 0 {
+        format!("-0x{:X}", -offset)
+    } else {
+        format!("+0x{:X}", offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::encode;
+
+    #[test]
+    fn decodes_each_encoding_form() {
+        assert_eq!(disassemble(encode(OpCode::NOOP, 0)), "NOOP");
+        assert_eq!(disassemble(encode(OpCode::LOAD_IMM, (3 << 20) | 0x1)), "LOAD_IMM r3, 0x1");
+        assert_eq!(disassemble(encode(OpCode::PUSH, 7 << 20)), "PUSH r7");
+        assert_eq!(disassemble(encode(OpCode::MOV, (1 << 20) | (2 << 15))), "MOV r1, r2");
+        assert_eq!(
+            disassemble(encode(OpCode::ADD, (1 << 20) | (2 << 15) | (3 << 10))),
+            "ADD r1, r2, r3"
+        );
+        assert_eq!(disassemble(encode(OpCode::JUMP_IMM, 0xF00)), "JUMP_IMM 0x00000F00");
+        assert_eq!(disassemble(encode(OpCode::JUMP_REL, 0x1FFFFF4)), "JUMP_REL -0xC");
+        assert_eq!(disassemble(encode(OpCode::BTST, (1 << 20) | (7 << 15))), "BTST r1, 7");
+        assert_eq!(
+            disassemble(encode(OpCode::IRPT_SEND, (1 << 20) | (5 << 15) | (2 << 10))),
+            "IRPT_SEND core 1, type 5, vector 2"
+        );
+        assert_eq!(disassemble(0x7F << 25), ".word 0xFE000000");
+    }
+    #[test]
+    fn listing_keeps_load_full_constants_out_of_the_instructions() {
+        let words = [encode(OpCode::LOAD_FULL, 1 << 20), 0x1E00_0000, encode(OpCode::NOOP, 0)];
+        assert_eq!(
+            listing(&words, 0x100),
+            [(0x100, "LOAD_FULL r1, 0x1E000000".to_string()), (0x108, "NOOP".to_string())]
+        );
+        // A constant cut off by the end of the words
+        assert_eq!(listing(&words[..1], 0x100), [(0x100, "LOAD_FULL r1".to_string())]);
+    }
+}
@@ -0,0 +1,125 @@
+/// Memory mapped DMA engine that fills or copies RAM in a single host operation, instead of the
+/// guest storing one byte at a time.
+///
+/// Like the timer, every byte offset of the device maps to one whole register:
+///
+/// - `0`: Destination address.
+/// - `1`: Source address for copies, the lowest byte is the fill value for fills.
+/// - `2`: Length in bytes.
+/// - `3`: Command. Writing 1 fills the destination, writing 2 copies the source to the
+///   destination. Overlapping ranges are copied as if through a temporary buffer. Reads 0.
+/// - `4`: Status of the last command (read-only). 0 on success, 1 if it failed.
+///
+/// Commands complete before the write to the command register returns, so the status can be read
+/// right after. Transfers work on RAM directly and bypass the memory mapped devices.
+pub struct Dma {
+    pub registers: [u32; 5],
+    pub ram: std::sync::Arc<std::sync::RwLock<crate::memory::Memory>>,
+}
+
+impl Dma {
+    pub fn new(ram: std::sync::Arc<std::sync::RwLock<crate::memory::Memory>>) -> Self {
+        info!("Created Dma");
+        Self {
+            registers: [0; 5],
+            ram,
+        }
+    }
+
+    fn execute(&mut self, command: u32) -> Result<(), String> {
+        let [destination, source, length, ..] = self.registers.map(|register| register as usize);
+        let mut ram = self.ram.write().unwrap();
+        let size = ram.data.len();
+        let in_bounds = |start: usize| start.checked_add(length).is_some_and(|end| end <= size);
+        if !in_bounds(destination) {
+            return Err(format!("Destination 0x{:08X} + {} exceeds the address space", destination, length));
+        }
+        match command {
+            1 => {
+                ram.data[destination..destination + length].fill(source as u8);
+                Ok(())
+            }
+            2 => {
+                if !in_bounds(source) {
+                    return Err(format!("Source 0x{:08X} + {} exceeds the address space", source, length));
+                }
+                ram.data.copy_within(source..source + length, destination);
+                Ok(())
+            }
+            _ => Err(format!("Unknown command {}", command)),
+        }
+    }
+}
+
+impl crate::mmio::AddressSpace for Dma {
+    fn read8(&self, addr_offset: u32) -> u8 {
+        self.read32(addr_offset) as u8
+    }
+    fn read32(&self, addr_offset: u32) -> u32 {
+        match addr_offset {
+            0 | 1 | 2 | 4 => self.registers[addr_offset as usize],
+            3 => 0,
+            _ => {
+                error!("Address offset out of bounds!");
+                0
+            }
+        }
+    }
+    fn write8(&mut self, addr_offset: u32, value: u8) {
+        self.write32(addr_offset, value as u32);
+    }
+    fn write32(&mut self, addr_offset: u32, value: u32) {
+        match addr_offset {
+            0..=2 => {
+                self.registers[addr_offset as usize] = value;
+                info!("Dma received value {} at offset {}", value, addr_offset);
+            }
+            3 => {
+                info!("Dma executing command {} on {} bytes", value, self.registers[2]);
+                self.registers[4] = match self.execute(value) {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        error!("Dma command {} failed: {}", value, e);
+                        1
+                    }
+                };
+            }
+            4 => error!("Dma status is read-only, ignoring write of {}", value),
+            _ => error!("Address offset out of bounds!"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::AddressSpace;
+
+    #[test]
+    fn fills_and_copies_ram() {
+        let ram = std::sync::Arc::new(std::sync::RwLock::new(crate::memory::Memory::empty(0x1000)));
+        let mut dma = Dma::new(ram.clone());
+
+        dma.write32(0, 0x100);
+        dma.write32(1, 0x1AB);
+        dma.write32(2, 0x10);
+        dma.write32(3, 1);
+        assert_eq!(dma.read32(4), 0);
+        assert_eq!(&ram.read().unwrap().data[0x100..0x110], &[0xAB; 0x10]);
+        assert_eq!(ram.read().unwrap().data[0x110], 0);
+
+        // Overlapping copy one byte up
+        ram.write().unwrap().data[0x100] = 0x01;
+        dma.write32(0, 0x101);
+        dma.write32(1, 0x100);
+        dma.write32(3, 2);
+        assert_eq!(dma.read32(4), 0);
+        assert_eq!(ram.read().unwrap().data[0x100..0x103], [0x01, 0x01, 0xAB]);
+        assert_eq!(ram.read().unwrap().data[0x110], 0xAB);
+
+        dma.write32(0, 0xFF8);
+        dma.write32(3, 1);
+        assert_eq!(dma.read32(4), 1);
+        assert_eq!(ram.read().unwrap().data[0xFF8..], [0; 8]);
+    }
+}
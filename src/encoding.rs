@@ -0,0 +1,96 @@
+//! Bit layout of instruction words.
+//!
+//! Instructions are 32-bit words stored least significant byte first. The opcode takes up the top
+//! 7 bits, the remaining 25 bits hold the operands in one of these forms:
+//!
+//! - R: `OP(7) - A(5) - B(5) - C(5) - xxx`, register fields at bits 20, 15 and 10. Instructions
+//!   with fewer operands leave the trailing fields zero.
+//! - I: `OP(7) - A(5) - IMM(20)`, a register and a 20-bit immediate.
+//! - J: `OP(7) - IMM(25)`, a single 25-bit immediate.
+//!
+//! The accessors name the register fields by position only. What a field means depends on the
+//! instruction: A is the destination of `ADD` but the source register of `STOR_BYTE`.
+//!
+//! Since the opcode starts at bit 25, the byte at the highest address of an instruction holds the
+//! opcode shifted left by one, with bit 24 of the operands in its lowest bit. Programs should be
+//! built with the helpers below rather than by writing single bytes.
+
+use crate::opcodes::OpCode;
+
+/// Bit position of the opcode.
+pub const OPCODE_SHIFT: u32 = 25;
+
+/// Combines `opcode` with already positioned operand bits.
+pub fn encode(opcode: OpCode, operands: u32) -> u32 {
+    (opcode as u32) << OPCODE_SHIFT | operands & 0x1FFFFFF
+}
+
+/// Encodes an R-form instruction.
+pub fn encode_r(opcode: OpCode, field_a: u32, field_b: u32, field_c: u32) -> u32 {
+    encode(opcode, (field_a & 0x1F) << 20 | (field_b & 0x1F) << 15 | (field_c & 0x1F) << 10)
+}
+
+/// Encodes an I-form instruction. Only the low 20 bits of `imm` are kept.
+pub fn encode_i(opcode: OpCode, field_a: u32, imm: u32) -> u32 {
+    encode(opcode, (field_a & 0x1F) << 20 | imm & 0xFFFFF)
+}
+
+/// Encodes a J-form instruction. Only the low 25 bits of `imm` are kept.
+pub fn encode_j(opcode: OpCode, imm: u32) -> u32 {
+    encode(opcode, imm)
+}
+
+/// Register field A.
+pub fn field_a(instruction: u32) -> u32 {
+    (instruction >> 20) & 0x1F
+}
+
+/// Register field B.
+pub fn field_b(instruction: u32) -> u32 {
+    (instruction >> 15) & 0x1F
+}
+
+/// Register field C.
+pub fn field_c(instruction: u32) -> u32 {
+    (instruction >> 10) & 0x1F
+}
+
+/// Immediate of an I-form instruction.
+pub fn imm20(instruction: u32) -> u32 {
+    instruction & 0xFFFFF
+}
+
+/// Immediate of a J-form instruction.
+pub fn imm25(instruction: u32) -> u32 {
+    instruction & 0x1FFFFFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_round_trip() {
+        let instruction = encode_r(OpCode::ADD, 1, 2, 31);
+        assert_eq!(OpCode::decode(instruction), Ok(OpCode::ADD));
+        assert_eq!((field_a(instruction), field_b(instruction), field_c(instruction)), (1, 2, 31));
+
+        let instruction = encode_i(OpCode::LOAD_IMM, 7, 0xABCDE);
+        assert_eq!(OpCode::decode(instruction), Ok(OpCode::LOAD_IMM));
+        assert_eq!((field_a(instruction), imm20(instruction)), (7, 0xABCDE));
+
+        let instruction = encode_j(OpCode::JUMP_IMM, 0xFFFF_FFFF);
+        assert_eq!(OpCode::decode(instruction), Ok(OpCode::JUMP_IMM));
+        assert_eq!(imm25(instruction), 0x1FFFFFF);
+    }
+
+    #[test]
+    fn load_imm_survives_a_trip_through_memory() {
+        let bytes = encode_i(OpCode::LOAD_IMM, 3, 0x12345).to_le_bytes();
+        assert_eq!(bytes[3], (OpCode::LOAD_IMM as u8) << 1);
+
+        let instruction = u32::from_le_bytes(bytes);
+        assert_eq!(OpCode::decode(instruction), Ok(OpCode::LOAD_IMM));
+        assert_eq!((field_a(instruction), imm20(instruction)), (3, 0x12345));
+    }
+}
@@ -1,27 +1,108 @@
-use rand::Rng;
-
-pub const SCREEN_WIDTH: usize = 1280;
-pub const SCREEN_HEIGHT: usize = 720;
-
+use rand::{Rng, SeedableRng};
+
+use crate::mmio::AddressSpace;
+
+/// Interrupt vector of the VSYNC IRQ. Its handler address goes into the vector table entry at
+/// `interrupt_vector_table + VSYNC_VECTOR * 4`, which is `0x14` with up to 4 cores.
+pub const VSYNC_VECTOR: u32 = 1;
+
+/// Number of overlay layers drawn on top of the frame buffer.
+pub const OVERLAY_LAYERS: usize = 3;
+
+/// # ==== Registers ====
+///
+/// Every byte offset of the device maps to one whole register:
+///
+/// - `0`: Frame buffer index for pixel draws, text cell for text draws.
+/// - `1`: ARGB pixel for pixel draws, char word (see `decode_char_u32`) for text draws.
+/// - `2`: Update register, a bitfield:
+///   - bit 0: Updates enabled. Clearing it clears the frame buffer to black once, afterwards it
+///     only changes through blits and triangles. With the `show_life` demo enabled, the demo is
+///     drawn every frame instead.
+///   - bits 1 - 2: Draw mode, the operation repeated every frame while updates are enabled.
+///     0 writes register 1 to pixel register 0 of the active layer, 1 blits like register 12,
+///     2 writes register 1 to text cell register 0 in GPU RAM. 3 is reserved and draws nothing.
+/// - `3`: Graphics mode. 0 = full, 1 = text, 2 = indexed.
+/// - `4`: Writing a non-zero value presents the frame.
+/// - `5`, `6`: VSYNC enable and target core, see `vsync_target`.
+/// - `7` - `11`: Source address in main RAM, width, height, x and y of a blit, see `blit`.
+///   Register 7 also holds the address of the triangle drawn by register 13.
+/// - `12`: Writing a non-zero value blits once.
+/// - `13`: Writing a non-zero value draws a triangle, see `fill_triangle`.
+/// - `14`, `15`: Palette index and color, see `palette`.
+///
+/// The layer registers live in a separate device, see `GpuLayers`.
 #[derive(Debug)]
 pub struct GPU {
     pub mode: GpuGraphicsMode,
+    /// GPU private RAM holding the text cells.
     pub ram: crate::memory::Memory,
-    pub frame_buffer: Box<[u32; 1280 * 720]>,
-    pub registers: [u32; 10], // fb_pointer, pixeldata, update-enable
+    /// Main RAM shared with the cores. Blits and triangles read their source from it, so guests
+    /// prepare them with ordinary stores.
+    pub main_ram: std::sync::Arc<std::sync::RwLock<crate::memory::Memory>>,
+    /// Bottom layer (layer 0). Always opaque, the alpha channel of its pixels is ignored.
+    pub frame_buffer: Vec<u32>,
+    /// Layers 1 and up, composited over the frame buffer in order by `present`. Their pixels are
+    /// blended according to their alpha channel and start out fully transparent.
+    pub layers: Vec<Box<[u32]>>,
+    /// Opacity of every overlay layer from 0 (invisible) to 255, applied on top of the per-pixel
+    /// alpha.
+    pub layer_opacity: Vec<u8>,
+    /// Layer all drawing operations write to, 0 being the frame buffer.
+    pub active_layer: usize,
+    /// Last complete frame, composited from all layers by `present`. This is what gets displayed.
+    pub front_buffer: Vec<u32>,
+    /// Rows of any layer written since the last `present`.
+    pub dirty_rows: Vec<bool>,
+    /// Rows of `front_buffer` changed by `present` since they were last copied by `copy_presented`.
+    pub presented_rows: Vec<bool>,
+    pub width: usize,
+    pub height: usize,
+    pub registers: [u32; 16],
     pub map_base: u32,
+    /// Source of all random fills, so a given seed always produces the same frames.
+    pub rng: rand::rngs::StdRng,
+    /// ARGB colors of the 256 frame buffer values in `GpuGraphicsMode::Indexed`. Starts out as a
+    /// grayscale ramp. Guests select an entry with register 14 and set it by writing its color to
+    /// register 15.
+    pub palette: [u32; 256],
+    /// Draw the `show_life` demo screen every frame while updates are disabled, instead of clearing
+    /// the frame buffer to black once.
+    pub demo: bool,
 }
 
 impl GPU {
-    pub fn init(map_base: u32) -> Self {
+    /// Creates a GPU whose random fills are seeded with `seed`, or from OS entropy if there is none.
+    pub fn init(
+        map_base: u32,
+        width: usize,
+        height: usize,
+        seed: Option<u64>,
+        main_ram: std::sync::Arc<std::sync::RwLock<crate::memory::Memory>>,
+    ) -> Self {
         let gpu = Self {
             mode: GpuGraphicsMode::Full,
             ram: crate::memory::Memory::empty(0x4000_0000),
-            frame_buffer: unsafe { Box::<[u32; 1280 * 720]>::new_uninit().assume_init() },
-            registers: [0u32; 10],
+            main_ram,
+            frame_buffer: vec![0u32; width * height],
+            layers: vec![vec![0u32; width * height].into_boxed_slice(); OVERLAY_LAYERS],
+            layer_opacity: vec![255; OVERLAY_LAYERS],
+            active_layer: 0,
+            front_buffer: vec![0u32; width * height],
+            dirty_rows: vec![false; height],
+            presented_rows: vec![false; height],
+            width,
+            height,
+            registers: [0u32; 16],
             map_base,
+            rng: match seed {
+                Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+                None => rand::rngs::StdRng::from_os_rng(),
+            },
+            palette: std::array::from_fn(|i| 0xFF000000 | (i as u32 * 0x010101)),
+            demo: false,
         };
-        info!("Created GPU");
+        info!("Created GPU with a resolution of {}x{}", width, height);
         return gpu;
     }
 
@@ -30,12 +111,149 @@ impl GPU {
         Ok(())
     }
 
+    fn graphics_mode(&self) -> GpuGraphicsMode {
+        match self.registers[3] {
+            1 => GpuGraphicsMode::Text,
+            2 => GpuGraphicsMode::Indexed,
+            _ => GpuGraphicsMode::Full,
+        }
+    }
+
+    /// Decodes the draw mode from bits 1 - 2 of the update register.
+    fn draw_mode(&self) -> Option<GpuDrawMode> {
+        match (self.registers[2] >> 1) & 0b11 {
+            0 => Some(GpuDrawMode::Pixel),
+            1 => Some(GpuDrawMode::Blit),
+            2 => Some(GpuDrawMode::Text),
+            _ => None,
+        }
+    }
+
     pub fn render(&mut self) {
-        if self.registers[2] == 0 {
-            self.show_life();
-        } else if self.registers[2] >= 1 {
-            self.frame_buffer[self.registers[0] as usize] = self.registers[1];
+        self.mode = self.graphics_mode();
+        let updates_enabled = self.registers[2] & 0b1 != 0;
+        if updates_enabled {
+            match self.draw_mode() {
+                Some(GpuDrawMode::Pixel) => {
+                    let index = self.registers[0] as usize;
+                    if index < self.frame_buffer.len() {
+                        self.active_buffer()[index] = self.registers[1];
+                        self.dirty_rows[index / self.width] = true;
+                    } else {
+                        error!("Frame buffer index {} is out of bounds, ignoring pixel", index);
+                    }
+                }
+                Some(GpuDrawMode::Blit) => {
+                    let [source, width, height, x, y] = [7, 8, 9, 10, 11].map(|i| self.registers[i]);
+                    self.blit(source, width, height, x, y);
+                }
+                Some(GpuDrawMode::Text) => {
+                    let cell = self.registers[0] as usize;
+                    if cell < self.ram.data.len() / 4 {
+                        self.ram.write32(cell as u32 * 4, self.registers[1]);
+                    } else {
+                        error!("Text cell {} is out of bounds, ignoring char", cell);
+                    }
+                }
+                None => {}
+            }
+        }
+        match self.mode {
+            GpuGraphicsMode::Full | GpuGraphicsMode::Indexed => {
+                if !updates_enabled && self.demo {
+                    self.show_life();
+                }
+            }
+            GpuGraphicsMode::Text => self.render_text(),
+        }
+    }
+
+    /// Draws the character grid stored at the start of GPU RAM. Each cell is a u32 as decoded by
+    /// `decode_char_u32`, laid out row by row with one cell per 8 pixels of screen width.
+    pub fn render_text(&mut self) {
+        let columns = self.width / 8;
+        for row in 0..self.height / 8 {
+            for column in 0..columns {
+                let cell = (row * columns + column) as u32;
+                let (char, color) = decode_char_u32(self.ram.read32(cell * 4));
+                self.draw_letter(char, color, column as u32, row as u32);
+            }
+        }
+    }
+
+    /// Core that receives an IRQ with `VSYNC_VECTOR` after every displayed frame. VSYNC is enabled
+    /// by writing a non-zero value to register 5 and sent to the core index in register 6. The
+    /// core only runs the handler once it enabled IRQs with `SEI`.
+    pub fn vsync_target(&self) -> Option<usize> {
+        (self.registers[5] != 0).then_some(self.registers[6] as usize)
+    }
+
+    /// Sends the VSYNC IRQ to `vsync_target` if VSYNC is enabled. Called once per displayed frame.
+    pub fn send_vsync(&self, senders: &[std::sync::mpsc::Sender<crate::cpu::Interrupt>]) {
+        let Some(target) = self.vsync_target() else {
+            return;
+        };
+        let msg = crate::cpu::Interrupt {
+            sender_id: crate::cpu::DEVICE_SENDER_ID,
+            interrupt_type: crate::cpu::InterruptType::Irq(VSYNC_VECTOR),
+            payload: None,
+        };
+        match senders.get(target) {
+            Some(sender) => {
+                let _ = sender.send(msg);
+            }
+            None => error!("VSYNC target core {} does not exist", target),
+        }
+    }
+
+    /// Buffer of the layer selected by `active_layer`.
+    fn active_buffer(&mut self) -> &mut [u32] {
+        match self.active_layer {
+            0 => &mut self.frame_buffer,
+            layer => &mut self.layers[layer - 1],
+        }
+    }
+
+    /// Publishes the current frame so it gets displayed, compositing the overlay layers over the
+    /// frame buffer in z-order. In `GpuGraphicsMode::Indexed` the low byte of every pixel of every
+    /// layer is looked up in the palette first. Index 0 is transparent in the overlay layers,
+    /// other entries blend according to the palette's alpha channel.
+    /// Only rows written since the last present are composited, except in indexed mode where
+    /// palette changes can affect every row.
+    pub fn present(&mut self) {
+        let indexed = self.graphics_mode() == GpuGraphicsMode::Indexed;
+        let lookup = |pixel: u32| if indexed { self.palette[(pixel & 0xFF) as usize] } else { pixel };
+        for row in 0..self.height {
+            if !indexed && !self.dirty_rows[row] {
+                continue;
+            }
+            for i in row * self.width..(row + 1) * self.width {
+                let mut color = Color::from_u32(lookup(self.frame_buffer[i]));
+                for (layer, &opacity) in self.layers.iter().zip(&self.layer_opacity) {
+                    if indexed && layer[i] & 0xFF == 0 {
+                        continue;
+                    }
+                    color = color.blend(Color::from_u32(lookup(layer[i])), opacity);
+                }
+                self.front_buffer[i] = color.to_argb_u32();
+            }
+            self.presented_rows[row] = true;
+        }
+        self.dirty_rows.fill(false);
+    }
+
+    /// Copies the rows of the front buffer that changed since the last call into `target`, which
+    /// has to be as large as the screen. Returns whether anything was copied.
+    pub fn copy_presented(&mut self, target: &mut [u32]) -> bool {
+        let mut copied = false;
+        for row in 0..self.height {
+            if std::mem::take(&mut self.presented_rows[row]) {
+                let pixels = row * self.width..(row + 1) * self.width;
+                target[pixels.clone()].copy_from_slice(&self.front_buffer[pixels]);
+                copied = true;
+            }
         }
+        copied
     }
 
     pub fn handle_errors(&self, error: Result<(), GpuError>) {}
@@ -50,86 +268,246 @@ impl GPU {
         }
     }
 
-    pub fn draw_letter(&mut self, char: char, pos_x: u32, pos_y: u32) {}
-
-    pub fn blit_pixel(&mut self, pos_x: usize, pos_y: usize, color: Color) {
-        self.frame_buffer[pos_y * SCREEN_WIDTH + pos_x] = color.to_argb_u32()
+    /// Draws `char` into the 8x8 character cell at column `pos_x` and row `pos_y`. Pixels not
+    /// covered by the glyph are cleared to black, characters without a glyph leave the cell blank.
+    pub fn draw_letter(&mut self, char: char, color: Color, pos_x: u32, pos_y: u32) {
+        let glyph = crate::font::glyph(char).unwrap_or(&[0; 8]);
+        for (y, row) in glyph.iter().enumerate() {
+            for x in 0..8 {
+                let pixel = if row & (1 << x) != 0 {
+                    color
+                } else {
+                    Color::from_argb(255, 0, 0, 0)
+                };
+                self.blit_pixel(pos_x as usize * 8 + x, pos_y as usize * 8 + y, pixel);
+            }
+        }
     }
 
-    pub fn show_life(&mut self) {
-        for pixel in self.frame_buffer.iter_mut() {
-            *pixel = Color::from_u32(rand::rng().random()).to_argb_u32();
+    /// Copies a `width` x `height` rectangle of ARGB words from main RAM at byte address `source`
+    /// into the frame buffer with its top left corner at `pos_x`, `pos_y`. The source is laid out
+    /// row by row without padding. Pixels outside the screen are skipped.
+    ///
+    /// Guests set up the blit in registers 7 (source), 8 (width), 9 (height), 10 (x) and 11 (y)
+    /// and start it by writing a non-zero value to register 12.
+    pub fn blit(&mut self, source: u32, width: u32, height: u32, pos_x: u32, pos_y: u32) {
+        let visible_width = width.min((self.width as u32).saturating_sub(pos_x));
+        let visible_height = height.min((self.height as u32).saturating_sub(pos_y));
+        let main_ram = self.main_ram.clone();
+        let ram = main_ram.read().unwrap();
+        for y in 0..visible_height {
+            for x in 0..visible_width {
+                let addr = source as u64 + (y as u64 * width as u64 + x as u64) * 4;
+                if addr + 4 > ram.data.len() as u64 {
+                    error!("Blit source 0x{:08X} exceeds main RAM", addr);
+                    return;
+                }
+                let color = Color::from_u32(ram.read32(addr as u32));
+                self.blit_pixel((pos_x + x) as usize, (pos_y + y) as usize, color);
+            }
         }
-        let size = 400; // Triangle side length in pixels (adjust to fit your window)
-        let cx: i32 = (SCREEN_WIDTH / 2) as i32;  // Center X
-        let cy: i32 = (SCREEN_HEIGHT / 2 + 50) as i32; // Center Y (shift down a bit for visibility)
-
-        let h = (size as f32 * (3f32.sqrt() / 2.0)) as i32; // Height of equilateral triangle
+    }
 
-        // Triangle vertices (screen coordinates)
-        let vx0 = cx - size / 2; // Red (bottom-left)
-        let vy0 = cy + h / 3;
-        let vx1 = cx;           // Green (top)
-        let vy1 = cy - (2 * h / 3);
-        let vx2 = cx + size / 2; // Blue (bottom-right)
-        let vy2 = cy + h / 3;
+    /// Fills the triangle between the screen coordinates `v0`, `v1` and `v2`, blending the
+    /// per-vertex `colors` across it (Gouraud shading). Pixels outside the screen are skipped.
+    ///
+    /// Guests store the triangle in main RAM as nine words `x0, y0, color0, x1, y1, color1, x2, y2,
+    /// color2`, with signed coordinates and ARGB colors, put its address into register 7 and draw
+    /// it by writing a non-zero value to register 13.
+    pub fn fill_triangle(&mut self, v0: (i32, i32), v1: (i32, i32), v2: (i32, i32), colors: [Color; 3]) {
+        // Widened so differences between guest supplied vertices can't overflow
+        let [(vx0, vy0), (vx1, vy1), (vx2, vy2)] = [v0, v1, v2].map(|(x, y)| (x as i64, y as i64));
+        let denom = (vy1 - vy2) as f32 * (vx0 - vx2) as f32 + (vx2 - vx1) as f32 * (vy0 - vy2) as f32;
+        if denom == 0.0 {
+            return;
+        }
 
         // Bounding box for faster looping
-        let min_x = vx0.min(vx1).min(vx2);
-        let max_x = vx0.max(vx1).max(vx2);
-        let min_y = vy0.min(vy1).min(vy2);
-        let max_y = vy0.max(vy1).max(vy2);
+        let min_x = vx0.min(vx1).min(vx2).max(0);
+        let max_x = vx0.max(vx1).max(vx2).min(self.width as i64 - 1);
+        let min_y = vy0.min(vy1).min(vy2).max(0);
+        let max_y = vy0.max(vy1).max(vy2).min(self.height as i64 - 1);
 
         for y in min_y..=max_y {
             for x in min_x..=max_x {
                 // Barycentric coordinates
-                let denom = (vy1 - vy2) as f32 * (vx0 - vx2) as f32 + (vx2 - vx1) as f32 * (vy0 - vy2) as f32;
                 let a = ((vy1 - vy2) as f32 * (x - vx2) as f32 + (vx2 - vx1) as f32 * (y - vy2) as f32) / denom;
                 let b = ((vy2 - vy0) as f32 * (x - vx2) as f32 + (vx0 - vx2) as f32 * (y - vy2) as f32) / denom;
                 let c = 1.0 - a - b;
 
                 // Inside triangle if all coords >= 0 (and <=1 implicitly)
                 if a >= 0.0 && b >= 0.0 && c >= 0.0 {
-                    // Scale to 0-255 (u8) and pack into u32 color (0xRRGGBB)
-                    let r = (a * 255.0) as u8;
-                    let g = (b * 255.0) as u8;
-                    let bl = (c * 255.0) as u8; // 'bl' to avoid keyword conflict
-
-                    let color: u32 = ((r as u32) << 16) | ((g as u32) << 8) | (bl as u32);
-
-                    self.blit_pixel(x as usize, y as usize, Color::from_u32(color));
+                    let blend = |channel: fn(&Color) -> u8| {
+                        (a * channel(&colors[0]) as f32 + b * channel(&colors[1]) as f32 + c * channel(&colors[2]) as f32) as u8
+                    };
+                    let color = Color::from_argb(blend(|c| c.a), blend(|c| c.r), blend(|c| c.g), blend(|c| c.b));
+                    self.blit_pixel(x as usize, y as usize, color);
                 }
             }
         }
-        for y in 0..255 {
-            for x in 0..255 {
-                self.blit_pixel(x, y, Color::from_argb(255, x as u8, x as u8, x as u8));
+    }
+
+    /// Draws the triangle described by the nine words at byte address `source` of main RAM, see
+    /// `fill_triangle`.
+    fn draw_triangle_command(&mut self, source: u32) {
+        let words: [u32; 9] = {
+            let ram = self.main_ram.read().unwrap();
+            if source as u64 + 9 * 4 > ram.data.len() as u64 {
+                error!("Triangle at 0x{:08X} exceeds main RAM", source);
+                return;
+            }
+            std::array::from_fn(|i| ram.read32(source + i as u32 * 4))
+        };
+        self.fill_triangle(
+            (words[0] as i32, words[1] as i32),
+            (words[3] as i32, words[4] as i32),
+            (words[6] as i32, words[7] as i32),
+            [Color::from_u32(words[2]), Color::from_u32(words[5]), Color::from_u32(words[8])],
+        );
+    }
+
+    /// Draws a single pixel to the active layer. Pixels outside the screen are ignored.
+    pub fn blit_pixel(&mut self, pos_x: usize, pos_y: usize, color: Color) {
+        if pos_x >= self.width || pos_y >= self.height {
+            return;
+        }
+        let width = self.width;
+        self.active_buffer()[pos_y * width + pos_x] = color.to_argb_u32();
+        self.dirty_rows[pos_y] = true;
+    }
+
+    pub fn show_life(&mut self) {
+        for pixel in self.frame_buffer.iter_mut() {
+            *pixel = Color::from_u32(self.rng.random()).to_argb_u32();
+        }
+        self.dirty_rows.fill(true);
+        let size = (self.width.min(self.height) * 5 / 9) as i32; // Triangle side length in pixels
+        let cx: i32 = (self.width / 2) as i32;  // Center X
+        let cy: i32 = (self.height / 2 + self.height / 14) as i32; // Center Y (shift down a bit for visibility)
+
+        let h = (size as f32 * (3f32.sqrt() / 2.0)) as i32; // Height of equilateral triangle
+
+        // Triangle vertices (screen coordinates)
+        let v0 = (cx - size / 2, cy + h / 3); // Red (bottom-left)
+        let v1 = (cx, cy - (2 * h / 3));      // Green (top)
+        let v2 = (cx + size / 2, cy + h / 3); // Blue (bottom-right)
+        self.fill_triangle(v0, v1, v2, [
+            Color::from_argb(255, 255, 0, 0),
+            Color::from_argb(255, 0, 255, 0),
+            Color::from_argb(255, 0, 0, 255),
+        ]);
+
+        // Grey gradient in the top left corner
+        let side = (self.width.min(self.height) / 3).max(1);
+        for y in 0..side {
+            for x in 0..side {
+                let shade = (x * 255 / side) as u8;
+                self.blit_pixel(x, y, Color::from_argb(255, shade, shade, shade));
             }
         }
+        self.present();
         std::thread::sleep(std::time::Duration::from_millis(1));
     }
 }
 
 impl crate::mmio::AddressSpace for GPU {
     fn read8(&self, addr_offset: u32) -> u8 {
-        0
+        if addr_offset as usize >= self.registers.len() {
+            error!("Address offset out of bounds!");
+            return 0;
+        }
+        self.registers[addr_offset as usize] as u8
     }
-    fn write8(&mut self, addr_offset: u32, value: u8) {
-        if addr_offset >= 0x10 {
+    fn read32(&self, addr_offset: u32) -> u32 {
+        if addr_offset as usize >= self.registers.len() {
             error!("Address offset out of bounds!");
-            return;
+            return 0;
         }
-        self.registers[addr_offset as usize] = value as u32;
-        info!("Received value {} at address {}", value, addr_offset)
+        self.registers[addr_offset as usize]
+    }
+    fn write8(&mut self, addr_offset: u32, value: u8) {
+        self.write32(addr_offset, value as u32);
     }
     fn write32(&mut self, addr_offset: u32, value: u32) {
-        if addr_offset >= 0x10 {
+        if addr_offset as usize >= self.registers.len() {
             error!("Address offset out of bounds!");
             return;
         }
 
+        let previous = self.registers[addr_offset as usize];
         self.registers[addr_offset as usize] = value;
-        info!("Received value {} at address {}", value, self.map_base - addr_offset)
+        info!("Received value {} at address 0x{:08X}", value, self.map_base + addr_offset);
+        // Cleared once instead of every frame, so blits and triangles drawn while updates are
+        // disabled stay visible
+        if addr_offset == 2 && previous & 0b1 != 0 && value & 0b1 == 0 && !self.demo {
+            self.frame_buffer.fill(Color::from_argb(255, 0, 0, 0).to_argb_u32());
+            self.dirty_rows.fill(true);
+        }
+        if addr_offset == 4 && value != 0 {
+            self.present();
+        }
+        if addr_offset == 12 && value != 0 {
+            let [source, width, height, x, y] = [7, 8, 9, 10, 11].map(|i| self.registers[i]);
+            self.blit(source, width, height, x, y);
+        }
+        if addr_offset == 13 && value != 0 {
+            self.draw_triangle_command(self.registers[7]);
+        }
+        if addr_offset == 15 {
+            self.palette[(self.registers[14] & 0xFF) as usize] = self.registers[15];
+        }
+    }
+}
+
+/// Layer registers of the GPU, mapped as a separate device next to the other devices because the
+/// main register block is full. Like the GPU, every byte offset maps to one whole register:
+///
+/// - `0`: Active layer all drawing goes to. 0 is the frame buffer, 1 and up the overlay layers.
+/// - `1`: Opacity of the active layer from 0 to 255. The frame buffer is always opaque.
+/// - `2`: Number of layers including the frame buffer (read-only).
+pub struct GpuLayers(pub std::sync::Arc<std::sync::Mutex<GPU>>);
+
+impl crate::mmio::AddressSpace for GpuLayers {
+    fn read8(&self, addr_offset: u32) -> u8 {
+        self.read32(addr_offset) as u8
+    }
+    fn read32(&self, addr_offset: u32) -> u32 {
+        let gpu = self.0.lock().unwrap();
+        match addr_offset {
+            0 => gpu.active_layer as u32,
+            1 => match gpu.active_layer {
+                0 => 255,
+                layer => gpu.layer_opacity[layer - 1] as u32,
+            },
+            2 => gpu.layers.len() as u32 + 1,
+            _ => {
+                error!("Address offset out of bounds!");
+                0
+            }
+        }
+    }
+    fn write8(&mut self, addr_offset: u32, value: u8) {
+        self.write32(addr_offset, value as u32);
+    }
+    fn write32(&mut self, addr_offset: u32, value: u32) {
+        let mut gpu = self.0.lock().unwrap();
+        match addr_offset {
+            0 if value as usize <= gpu.layers.len() => {
+                gpu.active_layer = value as usize;
+                info!("Drawing to GPU layer {}", value);
+            }
+            0 => error!("GPU layer {} does not exist", value),
+            1 => match gpu.active_layer {
+                0 => error!("The frame buffer is always opaque, ignoring opacity {}", value),
+                layer => {
+                    gpu.layer_opacity[layer - 1] = value.min(255) as u8;
+                    // The new opacity changes every pixel of the layer
+                    gpu.dirty_rows.fill(true);
+                }
+            },
+            2 => error!("GPU layer count is read-only, ignoring write of {}", value),
+            _ => error!("Address offset out of bounds!"),
+        }
     }
 }
 
@@ -168,6 +546,7 @@ pub fn decode_rgba_u32(char_word: u32) -> Color {
     return color;
 }
 
+#[derive(Clone, Copy)]
 pub struct Color {
     a: u8,
     r: u8,
@@ -192,15 +571,245 @@ impl Color {
     pub fn to_argb_u32(&self) -> u32 {
         return (self.a as u32) << 24 | (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32;
     }
+
+    /// Draws `top` over this color, weighted by its alpha channel scaled by `opacity`. The result
+    /// is opaque.
+    pub fn blend(&self, top: Color, opacity: u8) -> Color {
+        let alpha = top.a as u32 * opacity as u32 / 255;
+        let mix = |below: u8, above: u8| ((above as u32 * alpha + below as u32 * (255 - alpha)) / 255) as u8;
+        Color::from_argb(255, mix(self.r, top.r), mix(self.g, top.g), mix(self.b, top.b))
+    }
 }
 
-#[derive(Debug)]
+/// Operation the GPU repeats every frame while updates are enabled, see the update register.
+#[derive(Debug, PartialEq)]
+pub enum GpuDrawMode {
+    Pixel,
+    Blit,
+    Text,
+}
+
+#[derive(Debug, PartialEq)]
 pub enum GpuGraphicsMode {
     Text,
     Full,
+    /// Every pixel of the frame buffer is an index into `GPU::palette`.
+    Indexed,
 }
 
 #[derive(Debug, Display)]
 pub enum GpuError {
     Error,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn main_ram() -> std::sync::Arc<std::sync::RwLock<crate::memory::Memory>> {
+        std::sync::Arc::new(std::sync::RwLock::new(crate::memory::Memory::empty(0x1000)))
+    }
+
+    #[test]
+    fn same_seed_draws_the_same_noise() {
+        let mut a = GPU::init(0x1000, 640, 480, Some(42), main_ram());
+        let mut b = GPU::init(0x1000, 640, 480, Some(42), main_ram());
+        a.show_life();
+        b.show_life();
+        assert_eq!(a.front_buffer, b.front_buffer);
+    }
+
+    #[test]
+    fn show_life_scales_to_the_screen() {
+        let mut gpu = GPU::init(0x1000, 64, 48, Some(42), main_ram());
+        gpu.show_life();
+        // The gradient spans a third of the shorter side
+        assert_eq!(gpu.front_buffer[15], Color::from_argb(255, 239, 239, 239).to_argb_u32());
+    }
+
+    #[test]
+    fn vsync_is_sent_to_the_configured_core_once_enabled() {
+        let mut gpu = GPU::init(0x1000, 640, 480, Some(42), main_ram());
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..3).map(|_| std::sync::mpsc::channel()).unzip();
+        gpu.write32(6, 2);
+        gpu.send_vsync(&senders);
+        assert!(receivers[2].try_recv().is_err());
+
+        gpu.write32(5, 1);
+        gpu.send_vsync(&senders);
+        let interrupt = receivers[2].try_recv().unwrap();
+        assert!(matches!(interrupt.interrupt_type, crate::cpu::InterruptType::Irq(VSYNC_VECTOR)));
+        assert!(receivers[0].try_recv().is_err() && receivers[1].try_recv().is_err());
+    }
+
+    #[test]
+    fn blit_copies_a_clipped_rectangle_from_main_ram() {
+        let mut bus = crate::mmio::Bus::new_empty(0x2000);
+        let gpu = std::sync::Arc::new(std::sync::Mutex::new(GPU::init(0x1000, 640, 480, Some(42), bus.ram.clone())));
+        bus.map_device(crate::mmio::MmioRegion {
+            name: "GPU".to_string(),
+            base: 0x1000,
+            size: 0x10,
+            device: gpu.clone(),
+        }).unwrap();
+
+        // Stored and started like a guest would, through the bus
+        for i in 0..6 {
+            bus.write32(0x100 + i * 4, 0xFF000000 | i);
+        }
+        for (register, value) in [(7, 0x100), (8, 3), (9, 2), (10, 638), (11, 10), (12, 1)] {
+            bus.write32(0x1000 + register, value);
+        }
+        let gpu = gpu.lock().unwrap();
+        assert_eq!(gpu.frame_buffer[10 * 640 + 638..10 * 640 + 640], [0xFF000000, 0xFF000001]);
+        assert_eq!(gpu.frame_buffer[11 * 640 + 638..11 * 640 + 640], [0xFF000003, 0xFF000004]);
+        assert_eq!(gpu.frame_buffer[11 * 640 + 637], 0);
+    }
+
+    #[test]
+    fn triangle_command_interpolates_vertex_colors() {
+        let mut gpu = GPU::init(0x1000, 640, 480, Some(42), main_ram());
+        let triangle = [10, 10, 0xFFFF0000, 10, 110, 0xFFFF0000, 110, 10, 0xFF0000FF];
+        for (i, word) in triangle.into_iter().enumerate() {
+            gpu.main_ram.write().unwrap().write32(0x200 + i as u32 * 4, word);
+        }
+        gpu.write32(7, 0x200);
+        gpu.write32(13, 1);
+        assert_eq!(gpu.frame_buffer[10 * 640 + 10], 0xFFFF0000);
+        assert_eq!(gpu.frame_buffer[10 * 640 + 110], 0xFF0000FF);
+        let middle = Color::from_u32(gpu.frame_buffer[10 * 640 + 60]);
+        assert!(middle.r > 100 && middle.b > 100);
+        assert_eq!(gpu.frame_buffer[200 * 640 + 200], 0);
+    }
+
+    #[test]
+    fn triangle_with_extreme_vertices_does_not_overflow() {
+        let mut gpu = GPU::init(0x1000, 64, 64, Some(42), main_ram());
+        let triangle = [0x7FFFFFFF, 0xFFFFFFFF, 0xFFFF0000, 0xFFFFFFFF, 0x7FFFFFFF, 0xFFFF0000, 0x80000000, 0x80000000, 0xFFFF0000];
+        for (i, word) in triangle.into_iter().enumerate() {
+            gpu.main_ram.write().unwrap().write32(0x200 + i as u32 * 4, word);
+        }
+        gpu.write32(7, 0x200);
+        gpu.write32(13, 1);
+        gpu.fill_triangle((i32::MIN, 0), (i32::MAX, 0), (0, i32::MAX), [Color::from_u32(0xFFFFFFFF); 3]);
+        assert_eq!(gpu.frame_buffer[32 * 64 + 32], 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn indexed_mode_looks_up_the_palette_on_present() {
+        let mut gpu = GPU::init(0x1000, 640, 480, Some(42), main_ram());
+        gpu.write32(3, 2);
+        gpu.write32(14, 7);
+        gpu.write32(15, 0xFF123456);
+        gpu.frame_buffer[0] = 7;
+        gpu.frame_buffer[1] = 0x80;
+        gpu.dirty_rows[0] = true;
+        gpu.write32(4, 1);
+        assert_eq!(gpu.front_buffer[0], 0xFF123456);
+        assert_eq!(gpu.front_buffer[1], 0xFF808080);
+    }
+
+    #[test]
+    fn present_only_copies_changed_rows() {
+        let mut gpu = GPU::init(0x1000, 640, 480, Some(42), main_ram());
+        let mut shown = vec![0u32; 640 * 480];
+        gpu.blit_pixel(5, 3, Color::from_u32(0xFFABCDEF));
+        assert!(!gpu.copy_presented(&mut shown));
+        gpu.write32(4, 1);
+        assert_eq!(gpu.presented_rows.iter().filter(|&&row| row).count(), 1);
+        assert!(gpu.copy_presented(&mut shown));
+        assert_eq!(shown[3 * 640 + 5], 0xFFABCDEF);
+        assert!(!gpu.copy_presented(&mut shown));
+    }
+
+    /// Compares cloning the whole front buffer every frame against copying the one changed row.
+    /// Run with `cargo test --release -- --ignored --nocapture bench_present`.
+    #[test]
+    #[ignore]
+    fn bench_present() {
+        let mut gpu = GPU::init(0x1000, 1280, 720, Some(42), main_ram());
+        let mut shown = vec![0u32; 1280 * 720];
+        let frames = 1000;
+
+        let start = std::time::Instant::now();
+        for i in 0..frames {
+            gpu.blit_pixel(i % 1280, i % 720, Color::from_u32(i as u32));
+            gpu.front_buffer.copy_from_slice(&gpu.frame_buffer);
+            std::hint::black_box(gpu.front_buffer.clone());
+        }
+        let full_time = start.elapsed() / frames as u32;
+
+        let start = std::time::Instant::now();
+        for i in 0..frames {
+            gpu.blit_pixel(i % 1280, i % 720, Color::from_u32(i as u32));
+            gpu.present();
+            gpu.copy_presented(&mut shown);
+            std::hint::black_box(&shown);
+        }
+        let dirty_time = start.elapsed() / frames as u32;
+
+        println!("full copy: {:?} per frame, dirty rows: {:?} per frame", full_time, dirty_time);
+    }
+
+    #[test]
+    fn overlay_layers_blend_by_alpha_and_opacity() {
+        let gpu = std::sync::Arc::new(std::sync::Mutex::new(GPU::init(0x1000, 640, 480, Some(42), main_ram())));
+        let mut layers = GpuLayers(gpu.clone());
+        gpu.lock().unwrap().blit_pixel(0, 0, Color::from_u32(0xFF0000FF));
+        gpu.lock().unwrap().blit_pixel(1, 0, Color::from_u32(0xFF0000FF));
+        layers.write32(0, 2);
+        layers.write32(1, 128);
+        gpu.lock().unwrap().blit_pixel(0, 0, Color::from_u32(0xFFFF0000));
+        layers.write32(0, 9);
+        assert_eq!(layers.read32(0), 2);
+
+        let mut gpu = gpu.lock().unwrap();
+        gpu.present();
+        assert_eq!(gpu.front_buffer[0], 0xFF80007F);
+        assert_eq!(gpu.front_buffer[1], 0xFF0000FF);
+    }
+
+    #[test]
+    fn update_register_selects_the_draw_mode() {
+        let mut gpu = GPU::init(0x1000, 640, 480, Some(42), main_ram());
+        gpu.write32(0, 3);
+        gpu.write32(1, 0xFF112233);
+        gpu.write32(2, 0b101);
+        gpu.render();
+        assert_eq!(gpu.ram.read32(12), 0xFF112233);
+        assert_eq!(gpu.frame_buffer[3], 0);
+
+        gpu.write32(2, 0b001);
+        gpu.render();
+        assert_eq!(gpu.frame_buffer[3], 0xFF112233);
+    }
+
+    #[test]
+    fn out_of_bounds_pixels_are_ignored() {
+        let mut gpu = GPU::init(0x1000, 640, 480, Some(42), main_ram());
+        gpu.blit_pixel(640, 0, Color::from_u32(0xFFFFFFFF));
+        gpu.blit_pixel(0, 480, Color::from_u32(0xFFFFFFFF));
+        gpu.write32(0, u32::MAX);
+        gpu.write32(2, 0b001);
+        gpu.render();
+        gpu.write32(2, 0b101);
+        gpu.render();
+        assert!(gpu.frame_buffer.iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn disabling_updates_clears_to_black_once_without_demo() {
+        let black = Color::from_argb(255, 0, 0, 0).to_argb_u32();
+        let mut gpu = GPU::init(0x1000, 640, 480, Some(42), main_ram());
+        gpu.write32(2, 1);
+        gpu.frame_buffer.fill(0xFFFFFFFF);
+        gpu.write32(2, 0);
+        assert!(gpu.frame_buffer.iter().all(|&pixel| pixel == black));
+
+        // Drawn while updates are disabled, like a blit or triangle command
+        gpu.blit_pixel(3, 0, Color::from_u32(0xFF123456));
+        gpu.render();
+        assert_eq!(gpu.frame_buffer[3], 0xFF123456);
+        assert_eq!(gpu.frame_buffer[4], black);
+    }
+}
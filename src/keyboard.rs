@@ -0,0 +1,68 @@
+/// Memory mapped keyboard exposing which keys are currently held down.
+///
+/// The device is a read-only 16 byte (128 bit) bitmap. Bit `n` of the bitmap is found in byte
+/// `n / 8` at bit position `n % 8` and is set while the key with code `n` is held. Key codes
+/// follow `minifb::Key`:
+///
+/// - `0 - 9`: Number keys 0 - 9
+/// - `10 - 35`: Letters A - Z
+/// - `36 - 50`: F1 - F15
+/// - `51 - 54`: Down, Left, Right, Up
+/// - `55 - 105`: Punctuation, editing, modifier and numpad keys in `minifb::Key` order, e.g.
+///   Enter is 69, Escape is 70 and Space is 77
+/// - `106`: Any key minifb doesn't recognize
+#[derive(Debug)]
+pub struct Keyboard {
+    pub state: [u8; 16],
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        info!("Created Keyboard");
+        Self {
+            state: [0; 16],
+        }
+    }
+
+    /// Replaces the key state with the given set of held keys.
+    #[cfg(feature = "gpu")]
+    pub fn set_keys(&mut self, keys: &[minifb::Key]) {
+        self.state = [0; 16];
+        for &key in keys {
+            let code = key as usize;
+            if code < self.state.len() * 8 {
+                self.state[code / 8] |= 1 << (code % 8);
+            }
+        }
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl crate::mmio::AddressSpace for Keyboard {
+    fn read8(&self, addr_offset: u32) -> u8 {
+        if addr_offset as usize >= self.state.len() {
+            error!("Address offset out of bounds!");
+            return 0;
+        }
+        self.state[addr_offset as usize]
+    }
+    fn read32(&self, addr_offset: u32) -> u32 {
+        u32::from_le_bytes([
+            self.read8(addr_offset),
+            self.read8(addr_offset + 1),
+            self.read8(addr_offset + 2),
+            self.read8(addr_offset + 3),
+        ])
+    }
+    fn write8(&mut self, addr_offset: u32, value: u8) {
+        error!("Keyboard is read-only, ignoring write of {} at offset {}", value, addr_offset);
+    }
+    fn write32(&mut self, addr_offset: u32, value: u32) {
+        error!("Keyboard is read-only, ignoring write of {} at offset {}", value, addr_offset);
+    }
+}
@@ -0,0 +1,45 @@
+//! A multi-core 32-bit virtual machine with memory mapped devices.
+//!
+//! `VM` ties everything together: build one with `VM::new` from a `memory::Memory` image (or the
+//! built-in demo), adjust its public fields and call `VM::run`, which blocks until the VM stops
+//! and hands it back so its final state can be inspected. Programs can be built with
+//! `asm::assemble` and inspected with `disasm::disassemble`.
+
+#![allow(unused_assignments)]
+
+#[macro_use]
+extern crate derive_more;
+
+#[macro_use]
+extern crate tracing;
+
+pub mod vm;
+pub mod asm;
+pub mod cpu;
+// Only driven by the window loop, but kept so the GPU device stays mapped in headless builds
+#[cfg_attr(not(feature = "gpu"), allow(dead_code))]
+pub mod gpu;
+#[cfg_attr(not(feature = "gpu"), allow(dead_code))]
+pub mod font;
+pub mod keyboard;
+pub mod timer;
+pub mod console;
+pub mod block;
+pub mod sysinfo;
+pub mod dma;
+pub mod core;
+pub mod disasm;
+pub mod encoding;
+pub mod mmio;
+pub mod snapshot;
+pub mod memory;
+pub mod opcodes;
+
+pub use crate::asm::AsmError;
+pub use crate::core::Core;
+pub use crate::cpu::{ArithMode, CpuError, CpuErrorType, CpuMode, CPU};
+pub use crate::gpu::GpuError;
+pub use crate::memory::ElfError;
+pub use crate::mmio::{Bus, BusError};
+pub use crate::opcodes::OpCode;
+pub use crate::vm::VM;
@@ -1,8 +1,3 @@
-#![allow(unused_assignments)]
-
-#[macro_use]
-extern crate derive_more;
-
 #[macro_use]
 extern crate tracing;
 
@@ -10,20 +5,116 @@ use clap::Parser;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{fmt, layer::SubscriberExt, prelude::*, EnvFilter};
 
-use crate::opcodes::OpCode;
-
-mod vm;
-mod cpu;
-mod gpu;
-mod core;
-mod mmio;
-mod memory;
-mod opcodes;
+use rusty_vm_2::{asm, cpu, memory, snapshot, vm};
 
 #[derive(Parser)]
 struct Args {
     #[arg(long)]
-    log_file: Option<String>
+    log_file: Option<String>,
+    /// ROM image to load into memory instead of the built-in demo
+    #[arg(long, group = "program")]
+    rom: Option<String>,
+    /// Assembly source file to assemble and run instead of the built-in demo
+    #[arg(long, group = "program")]
+    asm: Option<String>,
+    /// 32-bit ELF executable to load instead of the built-in demo
+    #[arg(long, group = "program")]
+    elf: Option<String>,
+    /// Instructions executed per second by each core. 0 runs as fast as possible
+    #[arg(long, default_value_t = 100)]
+    clock_hz: u32,
+    /// Wait for ENTER before every instruction, printing it and the register file first
+    #[arg(long)]
+    step: bool,
+    /// Write the VM state to this file when the VM stops
+    #[arg(long)]
+    save_state: Option<String>,
+    /// Resume from a VM state written with --save-state
+    #[arg(long)]
+    load_state: Option<String>,
+    /// Run without a window until every core is halted or idle. Always on in builds without the
+    /// gpu feature
+    #[arg(long)]
+    headless: bool,
+    /// Host file backing the block device. Created if it doesn't exist
+    #[arg(long)]
+    disk: Option<String>,
+    /// Stop the VM after all cores together executed this many instructions
+    #[arg(long)]
+    max_cycles: Option<u64>,
+    /// RAM range dumped to the current directory when an error occurs in Debug mode, as START..END.
+    /// Defaults to the stack region
+    #[arg(long, value_parser = parse_range)]
+    dump_range: Option<std::ops::Range<u32>>,
+    /// Log every write to this address. Can be given multiple times
+    #[arg(long, value_parser = parse_address)]
+    watch: Vec<u32>,
+    /// Number of CPU cores
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..=rusty_vm_2::core::MAX_CORES as i64))]
+    cores: u32,
+    /// Lowest address of the stack region. Defaults to a quarter of the memory size
+    #[arg(long, value_parser = parse_address)]
+    stack_base: Option<u32>,
+    /// Size of the stack region in bytes. Defaults to a quarter of the memory size
+    #[arg(long, value_parser = parse_address)]
+    stack_size: Option<u32>,
+    /// Size of the VM address space in bytes
+    #[arg(long, default_value = "0x100000000", value_parser = parse_memory_size)]
+    memory_size: usize,
+    /// Whether arithmetic overflows are reported as errors or wrap around silently
+    #[arg(long, value_enum, default_value_t = cpu::ArithMode::Checked)]
+    arith: cpu::ArithMode,
+    /// Screen resolution as WIDTHxHEIGHT
+    #[arg(long, default_value = "1280x720", value_parser = parse_resolution)]
+    resolution: (usize, usize),
+    /// Show the GPU demo screen while the guest hasn't enabled screen updates
+    #[arg(long)]
+    demo: bool,
+    /// Seed for the random noise of the GPU demo screen. Drawn from OS entropy if not given
+    #[arg(long)]
+    seed: Option<u64>
+}
+
+fn parse_resolution(value: &str) -> Result<(usize, usize), String> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| format!("Expected WIDTHxHEIGHT, got {value}"))?;
+    let width: usize = width.parse().map_err(|_| format!("Invalid width: {width}"))?;
+    let height: usize = height.parse().map_err(|_| format!("Invalid height: {height}"))?;
+    if width == 0 || height == 0 {
+        return Err("Resolution must not be zero".to_string());
+    }
+    Ok((width, height))
+}
+
+fn parse_address(value: &str) -> Result<u32, String> {
+    cpu::parse_number(value).ok_or_else(|| format!("Invalid address: {value}"))
+}
+
+fn parse_memory_size(value: &str) -> Result<usize, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    let size = parsed.map_err(|_| format!("Invalid size: {value}"))?;
+    if !(vm::MIN_MEMORY_SIZE..=0x1_0000_0000).contains(&size) {
+        return Err(format!(
+            "Memory size must be between 0x{:X} and 0x100000000 bytes",
+            vm::MIN_MEMORY_SIZE
+        ));
+    }
+    Ok(size)
+}
+
+fn parse_range(value: &str) -> Result<std::ops::Range<u32>, String> {
+    let (start, end) = value
+        .split_once("..")
+        .ok_or_else(|| format!("Expected START..END, got {value}"))?;
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+    if start > end {
+        return Err("Range start must not be after its end".to_string());
+    }
+    Ok(start..end)
 }
 
 fn main() {
@@ -32,13 +123,116 @@ fn main() {
     let stdout_layer = fmt::layer().with_writer(std::io::stdout).with_filter(filter.clone());
     let log_file_path = args.log_file.unwrap_or_else(|| "log.json".to_string());
     let log_file = std::fs::File::create(log_file_path).unwrap();
-    let (non_blocking,_guard) = tracing_appender::non_blocking(log_file);
-    //let json_layer = fmt::layer().json().with_writer(non_blocking).with_filter(filter);
+    // The guard flushes the log file when dropped, so it has to live until the VM stopped
+    let (non_blocking, _guard) = tracing_appender::non_blocking(log_file);
+    let json_layer = fmt::layer().json().with_writer(non_blocking).with_filter(filter);
     tracing_subscriber::registry()
         .with(stdout_layer)
-        //.with(json_layer)
+        .with(json_layer)
         .init();
 
-    let vm = vm::VM::new();
-    vm.run();
+    let memory = if let Some(path) = args.asm {
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                error!("Could not read {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        match asm::assemble(&source) {
+            Ok(image) => match memory::Memory::from_bytes(&image, args.memory_size) {
+                Ok(memory) => Some(memory),
+                Err(e) => {
+                    error!("Could not load {}: {}", path, e);
+                    std::process::exit(1);
+                }
+            },
+            Err(e) => {
+                error!("Could not assemble {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    } else if let Some(path) = args.elf {
+        match memory::Memory::from_elf(&path, args.memory_size) {
+            Ok(memory) => Some(memory),
+            Err(e) => {
+                error!("Could not load {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    } else if let Some(path) = args.rom {
+        let rom = match memory::Memory::get_data_from_file(&path) {
+            Ok(rom) => rom,
+            Err(e) => {
+                error!("Could not read {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        match memory::Memory::from_bytes(&rom, args.memory_size) {
+            Ok(memory) => Some(memory),
+            Err(e) => {
+                error!("Could not load {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    let mode = if args.step { cpu::CpuMode::Step } else { cpu::CpuMode::Debug };
+    let mut vm = match vm::VM::new(memory, args.memory_size, mode, args.arith, args.clock_hz, args.resolution, args.seed, args.cores as usize) {
+        Ok(vm) => vm,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let (default_base, default_size) = rusty_vm_2::core::default_stack(args.memory_size);
+    let stack_base = args.stack_base.unwrap_or(default_base);
+    let stack_size = args.stack_size.unwrap_or(default_size);
+    if let Err(e) = vm.cpu.set_stack(stack_base, stack_size) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    if let Some(path) = args.load_state {
+        let restored = match snapshot::VmSnapshot::load(&path) {
+            Ok(snapshot) => vm.restore(snapshot),
+            Err(e) => {
+                error!("Could not load VM state from {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = restored {
+            error!("Could not restore VM state from {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    vm.save_state = args.save_state;
+    // Without the gpu feature there is no window to wait for
+    vm.headless = args.headless || !cfg!(feature = "gpu");
+    vm.gpu.lock().unwrap().demo = args.demo;
+    if let Some(path) = args.disk {
+        match std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path) {
+            Ok(file) => vm.disk = Some(file),
+            Err(e) => {
+                error!("Could not open disk {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    // Without an explicit range the dump follows the stack region. A stack ending at the top of
+    // the full address space loses its last byte, the range can't reach past u32::MAX
+    vm.cpu.dump_range = args.dump_range.unwrap_or(stack_base..stack_base.saturating_add(stack_size));
+    {
+        let mut bus = vm.bus.write().unwrap();
+        bus.watchpoints = args.watch.into_iter().collect();
+        bus.pause_on_watch = matches!(vm.cpu.mode, cpu::CpuMode::Debug | cpu::CpuMode::Step);
+    }
+    if let Some(limit) = args.max_cycles {
+        vm.cpu.set_max_cycles(limit);
+    }
+    if let Err(e) = vm.run() {
+        error!("VM stopped: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -1,5 +1,3 @@
-use std::io::Read;
-
 #[derive(Debug)]
 pub struct Memory {
     pub data: memmap2::MmapMut,
@@ -13,26 +11,124 @@ impl Memory {
             data: memory,
         }
     }
-    pub fn from_file(path: &str, size: usize) -> Self {
+    /// Allocates `size` bytes and copies `rom_data` to the start of it
+    pub fn from_bytes(rom_data: &[u8], size: usize) -> Result<Self, MemoryError> {
+        if rom_data.len() > size {
+            return Err(MemoryError::ImageTooLarge { len: rom_data.len(), size });
+        }
         info!("Allocating {} bytes of VM address space to system RAM...", size);
         let mut memory = memmap2::MmapOptions::new().len(size).map_anon().unwrap();
         info!("Loading ROM...");
-        let rom_data = Memory::get_data_from_file(path);
-        memory[0..rom_data.len()].copy_from_slice(rom_data.as_slice());
-        Self {
+        memory[0..rom_data.len()].copy_from_slice(rom_data);
+        Ok(Self {
             data: memory,
+        })
+    }
+    /// Loads a 32-bit little-endian ELF. Every `PT_LOAD` segment is copied to its `p_vaddr` and the
+    /// reset vector of core 0 at 0x0 is set to the entry point.
+    pub fn from_elf(path: &str, size: usize) -> Result<Self, ElfError> {
+        Memory::from_elf_bytes(&std::fs::read(path)?, size)
+    }
+
+    fn from_elf_bytes(elf: &[u8], size: usize) -> Result<Self, ElfError> {
+        let read_u16 = |offset: usize| -> Result<u16, ElfError> {
+            let bytes = elf.get(offset..offset + 2).ok_or(ElfError::Truncated)?;
+            Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let read_u32 = |offset: usize| -> Result<u32, ElfError> {
+            let bytes = elf.get(offset..offset + 4).ok_or(ElfError::Truncated)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        if elf.get(0..4) != Some(b"\x7FELF".as_slice()) {
+            return Err(ElfError::InvalidMagic);
+        }
+        if elf[4..].first() != Some(&1) {
+            return Err(ElfError::Not32Bit);
+        }
+        if elf[5..].first() != Some(&1) {
+            return Err(ElfError::NotLittleEndian);
         }
+        let entry = read_u32(0x18)?;
+        let ph_offset = read_u32(0x1C)? as usize;
+        let ph_entry_size = read_u16(0x2A)? as usize;
+        let ph_count = read_u16(0x2C)? as usize;
+
+        let mut memory = Memory::empty(size);
+        for i in 0..ph_count {
+            let header = ph_offset + i * ph_entry_size;
+            // PT_LOAD
+            if read_u32(header)? != 1 {
+                continue;
+            }
+            let offset = read_u32(header + 0x04)? as usize;
+            let vaddr = read_u32(header + 0x08)?;
+            let file_size = read_u32(header + 0x10)? as usize;
+            let mem_size = read_u32(header + 0x14)? as usize;
+            if vaddr as usize + mem_size.max(file_size) > size {
+                return Err(ElfError::SegmentOutOfBounds { vaddr, size: mem_size as u32 });
+            }
+            let segment = elf.get(offset..offset + file_size).ok_or(ElfError::Truncated)?;
+            info!("Loading ELF segment of {} bytes to 0x{:08X}...", file_size, vaddr);
+            memory.data[vaddr as usize..vaddr as usize + file_size].copy_from_slice(segment);
+        }
+        memory.data[0x0..0x4].copy_from_slice(&entry.to_le_bytes());
+        Ok(memory)
+    }
+
+    /// Writes the bytes in `range` to the file at `path`.
+    pub fn dump(&self, range: std::ops::Range<u32>, path: &str) -> std::io::Result<()> {
+        let data = self.data.get(range.start as usize..range.end as usize).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Range exceeds the memory size")
+        })?;
+        std::fs::write(path, data)
     }
-    pub fn get_data_from_file(path: &str) -> Box<[u8; 0x1_0000_0000]> {
-        let mut buf = vec![0u8];
-        let mut file = std::fs::File::open(&path)
-            .expect("Could not open File");
-        file.read(buf.as_mut_slice()).unwrap();
-        let mut rom = Box::new([0u8;0x1_0000_0000]);
-        for (i, &byte) in buf.iter().enumerate() {
-            rom[i] = byte;
+
+    /// Length of the memory up to and including the last non-zero byte
+    pub fn used_len(&self) -> usize {
+        // Scanned a word at a time, the address space is mostly untouched zero pages.
+        // SAFETY: every bit pattern is a valid u64
+        let (head, words, tail) = unsafe { self.data.align_to::<u64>() };
+        if let Some(last) = tail.iter().rposition(|&byte| byte != 0) {
+            return head.len() + words.len() * 8 + last + 1;
+        }
+        if let Some(word) = words.iter().rposition(|&word| word != 0) {
+            let start = head.len() + word * 8;
+            return start + self.data[start..start + 8].iter().rposition(|&byte| byte != 0).unwrap() + 1;
         }
-        return rom
+        head.iter().rposition(|&byte| byte != 0).map_or(0, |last| last + 1)
+    }
+    /// Reads a ROM image. Whether it fits into the memory is checked by `from_bytes`.
+    pub fn get_data_from_file(path: &str) -> std::io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+}
+
+#[derive(Debug, Display, Error, PartialEq)]
+pub enum MemoryError {
+    #[display("Image is {} bytes, which exceeds the {} byte memory", len, size)]
+    ImageTooLarge { len: usize, size: usize },
+}
+
+#[derive(Debug, Display, Error)]
+pub enum ElfError {
+    #[display("Could not read ELF file: {}", _0)]
+    Io(std::io::Error),
+    #[display("Not an ELF file")]
+    InvalidMagic,
+    #[display("Only 32-bit ELF files are supported")]
+    Not32Bit,
+    #[display("Only little-endian ELF files are supported")]
+    NotLittleEndian,
+    #[display("ELF file is truncated")]
+    Truncated,
+    #[display("Segment of {} bytes at 0x{:08X} exceeds the address space", size, vaddr)]
+    SegmentOutOfBounds { vaddr: u32, size: u32 },
+}
+
+impl From<std::io::Error> for ElfError {
+    fn from(error: std::io::Error) -> Self {
+        ElfError::Io(error)
     }
 }
 
@@ -40,10 +136,62 @@ impl crate::mmio::AddressSpace for Memory {
     fn read8(&self, addr: u32) -> u8 {
         self.data[addr as usize]
     }
+    fn read32(&self, addr: u32) -> u32 {
+        let addr = addr as usize;
+        u32::from_le_bytes(self.data[addr..addr + 4].try_into().unwrap())
+    }
     fn write8(&mut self, addr: u32, value: u8) {
         self.data[addr as usize] = value;
     }
     fn write32(&mut self, addr: u32, value: u32) {
-        panic!("Invalid operation")
+        let addr = addr as usize;
+        self.data[addr..addr + 4].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an ELF with a single `PT_LOAD` segment.
+    fn elf(entry: u32, vaddr: u32, segment: &[u8]) -> Vec<u8> {
+        let mut elf = vec![0u8; 0x54];
+        elf[0..4].copy_from_slice(b"\x7FELF");
+        elf[4] = 1;
+        elf[5] = 1;
+        elf[0x18..0x1C].copy_from_slice(&entry.to_le_bytes());
+        elf[0x1C..0x20].copy_from_slice(&0x34u32.to_le_bytes());
+        elf[0x2A..0x2C].copy_from_slice(&0x20u16.to_le_bytes());
+        elf[0x2C..0x2E].copy_from_slice(&1u16.to_le_bytes());
+        elf[0x34..0x38].copy_from_slice(&1u32.to_le_bytes());
+        elf[0x38..0x3C].copy_from_slice(&0x54u32.to_le_bytes());
+        elf[0x3C..0x40].copy_from_slice(&vaddr.to_le_bytes());
+        elf[0x44..0x48].copy_from_slice(&(segment.len() as u32).to_le_bytes());
+        elf[0x48..0x4C].copy_from_slice(&(segment.len() as u32).to_le_bytes());
+        elf.extend_from_slice(segment);
+        elf
+    }
+
+    #[test]
+    fn loads_elf_segments_and_entry_point() {
+        let memory = Memory::from_elf_bytes(&elf(0x104, 0x100, &[1, 2, 3, 4]), 0x1000).unwrap();
+        assert_eq!(&memory.data[0x100..0x104], &[1, 2, 3, 4]);
+        assert_eq!(&memory.data[0x0..0x4], &0x104u32.to_le_bytes());
+
+        assert!(matches!(
+            Memory::from_elf_bytes(&elf(0, 0xFFE, &[1, 2, 3, 4]), 0x1000),
+            Err(ElfError::SegmentOutOfBounds { vaddr: 0xFFE, size: 4 })
+        ));
+        assert!(matches!(Memory::from_elf_bytes(b"not an elf", 0x1000), Err(ElfError::InvalidMagic)));
+        assert!(matches!(Memory::from_elf("/nonexistent/program.elf", 0x1000), Err(ElfError::Io(_))));
+    }
+
+    #[test]
+    fn rejects_images_larger_than_the_memory() {
+        assert_eq!(Memory::from_bytes(&[1; 0x1000], 0x1000).unwrap().data[0xFFF], 1);
+        assert_eq!(
+            Memory::from_bytes(&[1; 0x1001], 0x1000).err(),
+            Some(MemoryError::ImageTooLarge { len: 0x1001, size: 0x1000 })
+        );
     }
 }
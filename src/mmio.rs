@@ -1,5 +1,6 @@
 pub trait AddressSpace {
     fn read8(&self, addr: u32) -> u8;
+    fn read32(&self, addr: u32) -> u32;
     fn write8(&mut self, addr: u32, value: u8);
     fn write32(&mut self, addr: u32, value: u32);
 }
@@ -15,51 +16,210 @@ pub struct MmioRegion {
 #[derive(Clone)]
 pub struct Bus {
     pub ram: std::sync::Arc<std::sync::RwLock<crate::memory::Memory>>,
-    pub regions: Vec<MmioRegion>
+    /// Mapped devices, sorted by `base` so lookups can use a binary search. Use `map_device` to
+    /// add devices.
+    pub regions: Vec<MmioRegion>,
+    /// Addresses whose writes are logged with their old and new value.
+    pub watchpoints: std::collections::HashSet<u32>,
+    /// Wait for ENTER after a watchpoint was hit.
+    pub pause_on_watch: bool
 }
 
 impl Bus {
     pub fn new_empty(size: usize) -> Self {
         Self {
             ram: std::sync::Arc::new(std::sync::RwLock::new(crate::memory::Memory::empty(size))),
-            regions: Vec::new()
+            regions: Vec::new(),
+            watchpoints: std::collections::HashSet::new(),
+            pause_on_watch: false
         }
     }
+
+    pub fn new(ram: crate::memory::Memory) -> Self {
+        Self {
+            ram: std::sync::Arc::new(std::sync::RwLock::new(ram)),
+            regions: Vec::new(),
+            watchpoints: std::collections::HashSet::new(),
+            pause_on_watch: false
+        }
+    }
+
+    /// Maps a device into the address space. Fails if its range overlaps an already mapped device.
+    pub fn map_device(&mut self, region: MmioRegion) -> Result<(), BusError> {
+        let end = region.base as u64 + region.size as u64;
+        for mapped in &self.regions {
+            if (region.base as u64) < mapped.base as u64 + mapped.size as u64 && end > mapped.base as u64 {
+                return Err(BusError::Overlap { name: region.name, other: mapped.name.clone() });
+            }
+        }
+        info!("Mapped device {} to 0x{:08X} - 0x{:08X}", region.name, region.base, end);
+        let index = self.regions.partition_point(|mapped| mapped.base < region.base);
+        self.regions.insert(index, region);
+        Ok(())
+    }
+
+    /// Reports a write of `size` bytes at `addr` if it touches a watched address.
+    fn check_watchpoints(&self, addr: u32, size: u32, old: u32, new: u32) {
+        if !(0..size).any(|offset| self.watchpoints.contains(&addr.wrapping_add(offset))) {
+            return;
+        }
+        warn!("Watchpoint hit at 0x{:08X}: 0x{:X} -> 0x{:X}", addr, old, new);
+        if self.pause_on_watch {
+            crate::cpu::wait_for_enter(|| info!("Press ENTER to continue"));
+        }
+    }
+
+    /// Finds the device mapped at `addr`, if any.
+    fn find_region(&self, addr: u32) -> Option<&MmioRegion> {
+        let index = self.regions.partition_point(|region| region.base <= addr);
+        let region = self.regions.get(index.checked_sub(1)?)?;
+        (addr - region.base < region.size).then_some(region)
+    }
+}
+
+#[derive(Debug, Display, Error, PartialEq)]
+pub enum BusError {
+    #[display("Device {} overlaps already mapped device {}", name, other)]
+    Overlap { name: String, other: String },
 }
 
 impl AddressSpace for Bus {
     fn read8(&self, addr: u32) -> u8 {
-        for device in &self.regions {
-            if addr >= device.base && addr < device.base + device.size {
-                info!("Reading from device {}", device.name);
-                return device.device.lock().unwrap().read8(addr - device.base);
-            }
+        if let Some(device) = self.find_region(addr) {
+            info!("Reading from device {}", device.name);
+            return device.device.lock().unwrap().read8(addr - device.base);
         }
         self.ram.read().unwrap().read8(addr)
     }
+    fn read32(&self, addr: u32) -> u32 {
+        if let Some(device) = self.find_region(addr) {
+            info!("Reading from device {}", device.name);
+            return device.device.lock().unwrap().read32(addr - device.base);
+        }
+        self.ram.read().unwrap().read32(addr)
+    }
     fn write8(&mut self, addr: u32, value: u8) {
         info!("Writing value {} to address {}", value, addr);
-        for device in &self.regions {
-            if addr >= device.base && addr < device.base + device.size {
-                info!("Forwarding to device {} at address {}...", device.name, addr);
-                device.device.lock().unwrap().write8(addr - device.base, value);
-                info!("Done");
-                return;
-            }
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, 1, self.read8(addr) as u32, value as u32);
+        }
+        if let Some(device) = self.find_region(addr) {
+            info!("Forwarding to device {} at address {}...", device.name, addr);
+            device.device.lock().unwrap().write8(addr - device.base, value);
+            info!("Done");
+            return;
         }
         self.ram.write().unwrap().write8(addr, value);
     }
 
     fn write32(&mut self, addr: u32, value: u32) {
         info!("Writing value {} to address {}", value, addr);
-        for device in &self.regions {
-            if addr >= device.base && addr < device.base + device.size {
-                info!("Forwarding to device {} at address {}...", device.name, addr);
-                device.device.lock().unwrap().write32(addr - device.base, value);
-                info!("Done");
-                return;
-            }
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, 4, self.read32(addr), value);
+        }
+        if let Some(device) = self.find_region(addr) {
+            info!("Forwarding to device {} at address {}...", device.name, addr);
+            device.device.lock().unwrap().write32(addr - device.base, value);
+            info!("Done");
+            return;
         }
         self.ram.write().unwrap().write32(addr, value);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(name: &str, base: u32, size: u32) -> MmioRegion {
+        MmioRegion {
+            name: name.to_string(),
+            base,
+            size,
+            device: std::sync::Arc::new(std::sync::Mutex::new(crate::memory::Memory::empty(size as usize))),
+        }
+    }
+
+    #[test]
+    fn map_device_rejects_overlapping_regions() {
+        let mut bus = Bus::new_empty(0x1000);
+        bus.map_device(region("A", 0x100, 0x10)).unwrap();
+        bus.map_device(region("B", 0x110, 0x10)).unwrap();
+        assert_eq!(
+            bus.map_device(region("C", 0x108, 0x10)),
+            Err(BusError::Overlap { name: "C".to_string(), other: "A".to_string() })
+        );
+        assert_eq!(bus.regions.len(), 2);
+    }
+
+    #[test]
+    fn find_region_matches_linear_scan() {
+        let mut bus = Bus::new_empty(0x1000);
+        bus.map_device(region("B", 0x200, 0x10)).unwrap();
+        bus.map_device(region("A", 0x100, 0x10)).unwrap();
+        for addr in [0x0, 0xFF, 0x100, 0x10F, 0x110, 0x200, 0x20F, 0x210, u32::MAX] {
+            let linear = bus.regions.iter().find(|r| addr >= r.base && addr < r.base + r.size);
+            assert_eq!(bus.find_region(addr).map(|r| &r.name), linear.map(|r| &r.name));
+        }
+    }
+
+    /// Compares the binary search against the previous linear scan with many mapped devices. Run
+    /// with `cargo test --release -- --ignored --nocapture bench_region_lookup`.
+    #[test]
+    #[ignore]
+    fn bench_region_lookup() {
+        let mut bus = Bus::new_empty(0x1000);
+        for i in 0..256 {
+            bus.map_device(region(&format!("Device {i}"), 0x1000 + i * 0x10, 0x10)).unwrap();
+        }
+        let addresses: Vec<u32> = (0..1_000_000u32).map(|i| 0x1000 + (i * 7919) % 0x1100).collect();
+
+        let start = std::time::Instant::now();
+        let linear = addresses
+            .iter()
+            .filter(|&&addr| bus.regions.iter().any(|r| addr >= r.base && addr < r.base + r.size))
+            .count();
+        let linear_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let binary = addresses.iter().filter(|&&addr| bus.find_region(addr).is_some()).count();
+        let binary_time = start.elapsed();
+
+        assert_eq!(linear, binary);
+        println!("linear scan: {:?}, binary search: {:?}", linear_time, binary_time);
+    }
+
+    /// Four cores pushing words to disjoint stack areas, once locking the bus per byte and once per
+    /// word. Run with `cargo test --release -- --ignored --nocapture bench_batched_stores`.
+    #[test]
+    #[ignore]
+    fn bench_batched_stores() {
+        fn run(batched: bool) -> std::time::Duration {
+            let bus = std::sync::Arc::new(std::sync::RwLock::new(Bus::new_empty(0x10_0000)));
+            let start = std::time::Instant::now();
+            let handles: Vec<_> = (0..4u32)
+                .map(|core| {
+                    let bus = bus.clone();
+                    std::thread::spawn(move || {
+                        let base = core * 0x4_0000;
+                        for i in 0..250_000u32 {
+                            let addr = base + (i % 0x1_0000) * 4;
+                            if batched {
+                                bus.write().unwrap().write32(addr, i);
+                            } else {
+                                for (offset, byte) in i.to_le_bytes().into_iter().enumerate() {
+                                    bus.write().unwrap().write8(addr + offset as u32, byte);
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            start.elapsed()
+        }
+        println!("per byte: {:?}, per word: {:?}", run(false), run(true));
+    }
+}
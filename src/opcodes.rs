@@ -11,11 +11,14 @@ pub enum OpCode {
     LOAD_IMM = 0x01,
 
     /// OP(7) - RDE(5) - IMM(20)
-    /// Loads an immediate 20-bit value to the upper 20 bits of register RDE.
+    /// Loads an immediate 20-bit value to the upper 20 bits of register RDE, bits 12 - 31. The
+    /// lower 12 bits of RDE are kept, so a `LOAD_IMM` of those bits followed by `LDUP_IMM` builds
+    /// any 32-bit value.
     LDUP_IMM = 0x02,
 
     /// OP(7) - RS1(5) - IMM(20)
-    /// Writes the value of register RS1 to the immediate 20-bit address.
+    /// Writes the full 32-bit value of register RS1 to the immediate 20-bit address, least
+    /// significant byte first.
     STOR_IMM = 0x03,
 
     /// OP(7) - RDE(5) - RS1(5) - xxx
@@ -23,9 +26,52 @@ pub enum OpCode {
     LOAD_BYTE = 0x04,
 
     /// OP(7) - RS1(5) - RS2(5) - xxx
-    /// Writes the value from register RS1 to the address stored in register RS2.
+    /// Writes the lowest byte of register RS2 to the address stored in register RS1.
     STOR_BYTE = 0x05,
 
+    /// OP(7) - RDE(5) - RS1(5) - xxx
+    /// Copies the content of register RS1 to register RDE.
+    MOV = 0x06,
+
+    /// OP(7) - RDE(5) - xxx
+    /// Sets register RDE to zero.
+    CLR = 0x07,
+
+    /// OP(7) - RDE(5) - RS1(5) - xxx
+    /// Loads a little-endian 32-bit word from the address stored in register RS1 to RDE in a
+    /// single bus access.
+    LOAD_WORD = 0x08,
+
+    /// OP(7) - RDE(5) - RS1(5) - xxx
+    /// Loads a little-endian 16-bit halfword from the address stored in register RS1 to RDE,
+    /// zero-extended. Like all loads and stores it doesn't require the address to be aligned.
+    LOAD_HALF = 0x0A,
+
+    /// OP(7) - RS1(5) - RS2(5) - xxx
+    /// Writes the lower 16 bits of register RS2 to the address stored in register RS1, least
+    /// significant byte first.
+    STOR_HALF = 0x0B,
+
+    /// OP(7) - RDE(5) - xxx
+    /// Loads the index of the executing core into register RDE. All cores boot from the same
+    /// memory, so programs use this to give each core its own work.
+    COREID = 0x0C,
+
+    /// OP(7) - IMM(25)
+    /// Sleeps the core for IMM milliseconds, so waiting loops don't have to spin. Interrupts sent
+    /// in the meantime are handled afterwards. Returns early when the VM stops. `YIELD 0` doesn't
+    /// sleep but lets the host schedule other threads first.
+    YIELD = 0x0D,
+
+    /// OP(7) - RDE(5) - xxx, IMM(32)
+    /// Loads the full 32-bit word following this instruction to register RDE. This is the only
+    /// instruction taking up two words, execution continues after the embedded constant.
+    LOAD_FULL = 0x0E,
+
+    /// OP(7) - RDE(5) - RS1(5) - xxx
+    /// Like `LOAD_HALF`, but sign-extends the halfword to 32 bits.
+    LOAD_HALF_S = 0x0F,
+
     /// OP(7) - IMM(25)
     /// Unconditionally jumps to the immediate 25-bit address.
     JUMP_IMM = 0x10,
@@ -53,24 +99,72 @@ pub enum OpCode {
     /// to the address the stack pointer is pointing to before jumping.
     BREQ_REG = 0x15,
 
-    /// OP(7) - SIG(1) - IMM(19) - xxx
-    /// Adds IMM to the program counter. SIG is a sign bit that determines whether IMM is negative (0) or positive(1). This
-    /// jump is unconditional.
+    /// OP(7) - OFF(25)
+    /// Adds OFF to the program counter. OFF is a two's complement offset that is sign-extended
+    /// from 25 to 32 bits, so setting its top bit jumps backwards. The offset is relative to the
+    /// address following this instruction. This jump is unconditional.
     JUMP_REL = 0x16,
 
-    /// OP(7) - SIG(1) - IMM(19) - xxx
-    /// Adds IMM to the program counter. SIG is a sign bit that determines whether IMM is negative (0) or positive(1). This
-    /// branch is unconditional. Writes the current position to the address the stack pointer is pointing to before branching.
-    BRAN_REL = 0x17,
+    /// OP(7) - OFF(25)
+    /// Calls the subroutine at OFF relative to the address following this instruction. Pushes that
+    /// address onto the stack before jumping, so `RTRN` or `RTRN_POP` return past the call. OFF is
+    /// encoded like the offset of `JUMP_REL`.
+    CALL_REL = 0x17,
+
+    /// OP(7) - IMM(25)
+    /// Jumps to the immediate 25-bit address if the equality flag is set.
+    BEQ_IMM = 0x18,
+
+    /// OP(7) - IMM(25)
+    /// Jumps to the immediate 25-bit address if the equality flag is not set.
+    BNE_IMM = 0x19,
+
+    /// OP(7) - IMM(25)
+    /// Jumps to the immediate 25-bit address if the less-than flag is set.
+    BLT_IMM = 0x1A,
+
+    /// OP(7) - IMM(25)
+    /// Jumps to the immediate 25-bit address if the less-than flag is not set.
+    BGE_IMM = 0x1B,
+
+    /// OP(7) - RDE(5) - xxx
+    /// Copies the stack pointer to register RDE. The stack grows upwards from the base of the stack
+    /// region (0x4000_0000 with the default 4 GiB of memory) and the stack pointer holds the address the next pushed
+    /// word is written to.
+    RDSP = 0x1C,
+
+    /// OP(7) - RS1(5) - xxx
+    /// Sets the stack pointer to the content of register RS1. Fails with `StackOpOutOfBounds` and
+    /// leaves the stack pointer unchanged if the value lies outside of the stack region, which is
+    /// the second quarter of the memory by default.
+    WRSP = 0x1D,
+
+    /// OP(7) - RDE(5) - xxx
+    /// Copies the program counter to register RDE. The program counter has already been advanced
+    /// past this instruction, so RDE receives the address of the next instruction.
+    RDPC = 0x1E,
 
     /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
-    /// Adds the contents of registers RS1 and RS2 and stores the result in register RDE.
+    /// Adds the contents of registers RS1 and RS2 and stores the result in register RDE. On
+    /// overflow RDE holds the wrapped low 32 bits of the sum. Sets the carry flag on unsigned and
+    /// the overflow flag on signed overflow.
     ADD = 0x20,
 
     /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
-    /// Subtracts the contents of registers RS1 and RS2 and stores the result in register RDE.
+    /// Subtracts the contents of registers RS1 and RS2 and stores the result in register RDE. Sets
+    /// the carry flag if the subtraction borrows and the overflow flag on signed overflow.
     SUB = 0x21,
 
+    /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
+    /// Multiplies the contents of registers RS1 and RS2 and stores the low 32 bits of the result
+    /// in register RDE.
+    MUL = 0x22,
+
+    /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
+    /// Divides the content of register RS1 by the content of register RS2 and stores the quotient
+    /// in register RDE.
+    DIV = 0x23,
+
     /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
     /// ANDs the content of register RS1 and RS2, storing the result to register RDE.
     AND = 0x24,
@@ -87,6 +181,98 @@ pub enum OpCode {
     /// XORs the content of register RS1 and RS2, storing the result to register RDE.
     XOR = 0x27,
 
+    /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
+    /// Divides the content of register RS1 by the content of register RS2 and stores the remainder
+    /// in register RDE.
+    MOD = 0x28,
+
+    /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
+    /// Shifts the content of register RS1 left by the amount in register RS2, storing the result to
+    /// register RDE. Only the low 5 bits of the shift amount are used.
+    SHL = 0x29,
+
+    /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
+    /// Logically shifts the content of register RS1 right by the amount in register RS2, storing
+    /// the result to register RDE. Only the low 5 bits of the shift amount are used.
+    SHR = 0x2A,
+
+    /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
+    /// Arithmetically shifts the content of register RS1 right by the amount in register RS2,
+    /// replicating the sign bit, and stores the result to register RDE. Only the low 5 bits of the
+    /// shift amount are used.
+    SAR = 0x2B,
+
+    /// OP(7) - RS1(5) - RS2(5) - xxx
+    /// Compares registers RS1 and RS2, setting the equality flag if they are equal and clearing it
+    /// otherwise.
+    CMP = 0x2C,
+
+    /// OP(7) - RDE(5) - IMM(20)
+    /// Adds the zero-extended 20-bit immediate value to register RDE.
+    ADDI = 0x2D,
+
+    /// OP(7) - RDE(5) - IMM(20)
+    /// Subtracts the zero-extended 20-bit immediate value from register RDE.
+    SUBI = 0x2E,
+
+    /// OP(7) - RDE(5) - RS1(5) - xxx
+    /// Inverts every bit of register RS1, storing the result to register RDE.
+    NOT = 0x2F,
+
+    /// OP(7) - RS1(5) - xxx
+    /// Pushes the content of register RS1 onto the stack.
+    PUSH = 0x30,
+
+    /// OP(7) - RDE(5) - xxx
+    /// Pops the last value from the stack into register RDE, freeing (setting to zero) the address
+    /// where the value was stored.
+    POP = 0x31,
+
+    /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
+    /// NANDs the content of register RS1 and RS2, storing the result to register RDE.
+    NAND = 0x32,
+
+    /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
+    /// NORs the content of register RS1 and RS2, storing the result to register RDE.
+    NOR = 0x33,
+
+    /// OP(7) - RS1(5) - RS2(5) - xxx
+    /// Interprets registers RS1 and RS2 as signed values, setting the less-than flag if RS1 is less
+    /// than RS2 and clearing it otherwise.
+    CMP_LT = 0x34,
+
+    /// OP(7) - RS1(5) - RS2(5) - xxx
+    /// Interprets registers RS1 and RS2 as unsigned values, setting the less-than flag if RS1 is
+    /// less than RS2 and clearing it otherwise.
+    CMP_LTU = 0x35,
+
+    /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
+    /// Atomically compares the word at the address stored in register RS1 with register RS2 and
+    /// replaces it with register RDE if they are equal. Sets the equality flag if the word was
+    /// replaced and clears it otherwise. No other core can access memory in between, which makes
+    /// this the building block for locks shared between cores.
+    CAS = 0x36,
+
+    /// OP(7) - RS1(5) - IMM(5) - xxx
+    /// Sets the equality flag to bit IMM of register RS1 and clears it if the bit is 0, so it can
+    /// be followed by `BEQ_IMM` or `BNE_IMM`. Bits are numbered from the least significant bit,
+    /// which is bit 0.
+    BTST = 0x37,
+
+    /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
+    /// Adds the contents of registers RS1 and RS2 plus the carry flag and stores the wrapped
+    /// result in register RDE, updating the carry and overflow flags like `ADD`. Never reports an
+    /// overflow error, so it can be chained to add numbers wider than 32 bits. Start the chain with
+    /// `CLC` and add the lowest words with `ADC` as well, since `ADD` traps on the carry in
+    /// `Checked` mode.
+    ADC = 0x3A,
+
+    /// OP(7) - RDE(5) - RS1(5) - RS2(5) - xxx
+    /// Subtracts the contents of register RS2 and the carry (borrow) flag from register RS1 and
+    /// stores the wrapped result in register RDE, updating the carry and overflow flags like
+    /// `SUB`. Never reports an overflow error.
+    SBC = 0x3B,
+
     /// OP(7) - xxx
     /// Used to return from a branch to the previous position. Reads the last value from the
     /// "stack" and sets the program counter to it.
@@ -109,10 +295,80 @@ pub enum OpCode {
     RSET_HARD = 0x41,
 
     /// OP(7) - xxx
+    /// Disables hardware IRQs. IRQs received while disabled are queued until they are enabled again.
+    CLI = 0x42,
+
+    /// OP(7) - xxx
+    /// Enables hardware IRQs, delivering queued IRQs in the order they arrived.
+    SEI = 0x43,
+
+    /// OP(7) - xxx
+    /// Clears the carry flag, so the first `ADC` or `SBC` of a chain adds no carry and subtracts
+    /// no borrow.
+    CLC = 0x44,
+
+    /// OP(7) - xxx
+    /// Shuts down the whole VM. Stops all cores, devices and the window, unlike `HALT`, which only
+    /// pauses the executing core.
+    SHUTDOWN = 0x4E,
+
+    /// OP(7) - xxx
+    /// Halts this core until it receives a Resume interrupt. Other cores keep running. Use
+    /// `SHUTDOWN` to stop the VM.
     HALT = 0x4F,
 
-    /// OP(7) - core_index(5) - type(5)
+    /// OP(7) - core_index(5) - type(5) - vector(5)
     /// Sends an interrupt to the core specified by core_index. The type of interrupt is determined
-    /// by the type specifier.
+    /// by the type specifier: 1 = Resume, 2 = Halt, 3 = SoftReset, 4 = HardReset, 5 = IRQ with the
+    /// given vector. Interrupts a core sends to itself are handled immediately instead of being
+    /// queued, so a self-targeted reset takes effect before the next instruction.
     IRPT_SEND = 0x50,
+
+    /// OP(7) - core_index(5) - RS1(5) - xxx
+    /// Sends the value of register RS1 to the core specified by core_index. The receiving core
+    /// stores it in its register r31 when it handles the interrupt and otherwise keeps running
+    /// unchanged, so r31 acts as a mailbox a receiver can poll, e.g. by clearing it first and
+    /// waiting until it's non-zero.
+    IRPT_SEND_DATA = 0x51,
+}
+
+impl OpCode {
+    /// Decodes the opcode in the top 7 bits of `instruction`, returning the raw opcode value if it
+    /// isn't a valid opcode.
+    pub fn decode(instruction: u32) -> Result<OpCode, u32> {
+        let value = (instruction >> 25) & 0x7F;
+        OpCode::try_from(value).map_err(|_| value)
+    }
+
+    /// Size of the instruction in bytes, including embedded constants.
+    pub fn length(&self) -> u32 {
+        match self {
+            OpCode::LOAD_FULL => 8,
+            _ => 4,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_covers_the_whole_opcode_space() {
+        let mut valid = 0;
+        for value in 0..0x80u32 {
+            for operands in [0, 0x1FFFFFF, 0x0A5A5A5] {
+                match OpCode::decode((value << 25) | operands) {
+                    Ok(opcode) => assert_eq!(opcode as u32, value),
+                    Err(invalid) => {
+                        assert_eq!(invalid, value);
+                        assert!(OpCode::try_from(value).is_err());
+                    }
+                }
+            }
+            valid += OpCode::decode(value << 25).is_ok() as u32;
+        }
+        assert!(valid > 0 && valid < 0x80);
+        assert_eq!(OpCode::decode(0xFFFF_FFFF), Err(0x7F));
+    }
 }
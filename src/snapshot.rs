@@ -0,0 +1,49 @@
+/// Complete state of a VM that can be written to disk and restored later.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct VmSnapshot {
+    pub cores: Vec<CoreState>,
+    /// Size of the memory the snapshot was taken from.
+    pub memory_size: usize,
+    /// RAM contents from address 0. Trailing zero bytes are not stored.
+    pub ram: Vec<u8>,
+    pub gpu_registers: Vec<u32>,
+}
+
+/// Reasons a snapshot can't be restored into a VM.
+#[derive(Debug, Display, Error, PartialEq)]
+pub enum RestoreError {
+    #[display("Snapshot has {} cores, but the VM has {}", snapshot, vm)]
+    CoreCount { snapshot: usize, vm: usize },
+    #[display("Snapshot was taken with {} bytes of memory, but the VM has {}", snapshot, vm)]
+    MemorySize { snapshot: usize, vm: usize },
+}
+
+/// Architectural state of a single core.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct CoreState {
+    pub program_counter: u32,
+    pub stack_pointer: u32,
+    pub registers: [u32; 32],
+    pub eq_flag: bool,
+    pub lt_flag: bool,
+    pub carry_flag: bool,
+    pub overflow_flag: bool,
+    pub busy: bool,
+    pub halted: bool,
+    pub interrupts_enabled: bool,
+    pub instructions_retired: u64,
+}
+
+impl VmSnapshot {
+    pub fn save(&self, path: &str) -> bincode::Result<()> {
+        info!("Saving VM state to {}...", path);
+        let file = std::fs::File::create(path)?;
+        bincode::serialize_into(std::io::BufWriter::new(file), self)
+    }
+
+    pub fn load(path: &str) -> bincode::Result<Self> {
+        info!("Loading VM state from {}...", path);
+        let file = std::fs::File::open(path)?;
+        bincode::deserialize_from(std::io::BufReader::new(file))
+    }
+}
@@ -0,0 +1,64 @@
+/// Read-only memory mapped block describing the VM configuration, so guests can adapt to it at
+/// runtime.
+///
+/// Like the timer, every byte offset of the device maps to one whole register:
+///
+/// - `0`: Version of the VM as `major << 16 | minor << 8 | patch`.
+/// - `1`: Low 32 bits of the memory size in bytes.
+/// - `2`: High 32 bits of the memory size in bytes. Only set for the full 4 GiB address space.
+/// - `3`: Number of cores.
+/// - `4`: Instructions executed per second by each core. 0 if the cores run as fast as possible.
+#[derive(Debug)]
+pub struct SysInfo {
+    pub registers: [u32; 5],
+}
+
+impl SysInfo {
+    pub fn new(memory_size: usize, cores: u32, clock_hz: u32) -> Self {
+        info!("Created SysInfo");
+        let version = |part: &str| part.parse::<u32>().unwrap_or(0);
+        Self {
+            registers: [
+                version(env!("CARGO_PKG_VERSION_MAJOR")) << 16
+                    | version(env!("CARGO_PKG_VERSION_MINOR")) << 8
+                    | version(env!("CARGO_PKG_VERSION_PATCH")),
+                memory_size as u64 as u32,
+                (memory_size as u64 >> 32) as u32,
+                cores,
+                clock_hz,
+            ],
+        }
+    }
+}
+
+impl crate::mmio::AddressSpace for SysInfo {
+    fn read8(&self, addr_offset: u32) -> u8 {
+        self.read32(addr_offset) as u8
+    }
+    fn read32(&self, addr_offset: u32) -> u32 {
+        if addr_offset as usize >= self.registers.len() {
+            error!("Address offset out of bounds!");
+            return 0;
+        }
+        self.registers[addr_offset as usize]
+    }
+    fn write8(&mut self, addr_offset: u32, value: u8) {
+        error!("SysInfo is read-only, ignoring write of {} at offset {}", value, addr_offset);
+    }
+    fn write32(&mut self, addr_offset: u32, value: u32) {
+        error!("SysInfo is read-only, ignoring write of {} at offset {}", value, addr_offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::AddressSpace;
+
+    #[test]
+    fn reports_configuration_and_ignores_writes() {
+        let mut sysinfo = SysInfo::new(0x1_0000_0000, 4, 100);
+        sysinfo.write32(3, 8);
+        assert_eq!([1, 2, 3, 4].map(|offset| sysinfo.read32(offset)), [0, 1, 4, 100]);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::cpu::{Interrupt, InterruptType};
+
+/// Memory mapped countdown timer that raises an interrupt on a core when it expires.
+///
+/// Like the GPU, every byte offset of the device maps to one whole register:
+///
+/// - `0`: Reload value in milliseconds. Writing it restarts the countdown.
+/// - `1`: Control. Bit 0 enables the timer, bit 1 makes it reload and keep running after it fired
+///   instead of disabling itself.
+/// - `2`: Index of the core that receives the interrupt.
+/// - `3`: Interrupt type specifier, using the same codes as `IRPT_SEND`.
+/// - `4`: Current counter value (read-only).
+/// - `5`: Vector number used when the interrupt type is an IRQ.
+#[derive(Debug)]
+pub struct Timer {
+    pub registers: [u32; 6],
+    pub senders: Vec<std::sync::mpsc::Sender<Interrupt>>,
+}
+
+impl Timer {
+    pub fn new(senders: Vec<std::sync::mpsc::Sender<Interrupt>>) -> Self {
+        info!("Created Timer");
+        Self {
+            registers: [0; 6],
+            senders,
+        }
+    }
+
+    /// Advances the timer by one millisecond, raising the configured interrupt once it reaches
+    /// zero.
+    pub fn tick(&mut self) {
+        if self.registers[1] & 0b1 == 0 || self.registers[4] == 0 {
+            return;
+        }
+        self.registers[4] -= 1;
+        if self.registers[4] > 0 {
+            return;
+        }
+
+        match InterruptType::from_code(self.registers[3], self.registers[5]) {
+            Some(interrupt_type) => {
+                let target = self.registers[2];
+                let msg = Interrupt {
+                    sender_id: crate::cpu::DEVICE_SENDER_ID,
+                    interrupt_type,
+                    payload: None,
+                };
+                info!("Timer sent {} to Core {}", msg, target);
+                match self.senders.get(target as usize) {
+                    Some(sender) => {
+                        let _ = sender.send(msg);
+                    }
+                    None => error!("Timer target core {} does not exist", target),
+                }
+            }
+            None => error!("Timer has unknown interrupt type {}", self.registers[3]),
+        }
+
+        if self.registers[1] & 0b10 != 0 {
+            self.registers[4] = self.registers[0];
+        } else {
+            self.registers[1] &= !0b1;
+        }
+    }
+}
+
+impl crate::mmio::AddressSpace for Timer {
+    fn read8(&self, addr_offset: u32) -> u8 {
+        self.read32(addr_offset) as u8
+    }
+    fn read32(&self, addr_offset: u32) -> u32 {
+        if addr_offset as usize >= self.registers.len() {
+            error!("Address offset out of bounds!");
+            return 0;
+        }
+        self.registers[addr_offset as usize]
+    }
+    fn write8(&mut self, addr_offset: u32, value: u8) {
+        self.write32(addr_offset, value as u32);
+    }
+    fn write32(&mut self, addr_offset: u32, value: u32) {
+        if addr_offset as usize >= self.registers.len() {
+            error!("Address offset out of bounds!");
+            return;
+        }
+        if addr_offset == 4 {
+            error!("Timer counter is read-only, ignoring write of {}", value);
+            return;
+        }
+        self.registers[addr_offset as usize] = value;
+        info!("Timer received value {} at offset {}", value, addr_offset);
+        if addr_offset <= 1 {
+            self.registers[4] = self.registers[0];
+        }
+    }
+}
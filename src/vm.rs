@@ -1,183 +1,311 @@
+/// Smallest supported address space. Covers the reset and interrupt vectors, the built-in demo and
+/// the device registers at 0x1000 - 0x1080.
+pub const MIN_MEMORY_SIZE: usize = 0x1_0000;
+
 pub struct VM {
     pub cpu: crate::cpu::CPU,
     pub bus: std::sync::Arc<std::sync::RwLock<crate::mmio::Bus>>,
-    pub running: std::sync::Arc<std::sync::atomic::AtomicBool>
+    pub running: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    /// Size of the window. Only used with the gpu feature, the GPU itself keeps its own copy.
+    #[cfg_attr(not(feature = "gpu"), allow(dead_code))]
+    pub resolution: (usize, usize),
+    /// Instructions executed per second by each core, reported to guests by the SysInfo device.
+    pub clock_hz: u32,
+    pub gpu: std::sync::Arc<std::sync::Mutex<crate::gpu::GPU>>,
+    /// File the VM state is written to once the VM stops.
+    pub save_state: Option<String>,
+    /// Run without a window. The VM stops once every core is halted or idle.
+    pub headless: bool,
+    /// Backing file of the block device. The device is only mapped if there is one.
+    pub disk: Option<std::fs::File>
 }
 
 impl VM {
-    pub fn new() -> Self {
-        let bus = crate::mmio::Bus::new_empty(0x1_0000_0000);
-        {
-            let mut memory = bus.ram.write().unwrap();
-
-            /*
-            memory.data[0x0] = 0x18; // Core 0 reset addr
-            memory.data[0x4] = 0x84; // Core 1 reset addr
-            memory.data[0x27] = (OpCode::IRPT_SEND as u8) << 1;
-            // xxxxxxxx xxxxxxxx xxxxxxxx xxxxxxxx
-            memory.data[0x26] = 0b00010000;
-            memory.data[0x25] = 0b10000000;
-
-            memory.data[0x87] = (OpCode::IRPT_SEND as u8) << 1;
-            memory.data[0x86] = 0b00000001;
-            memory.data[0x85] = 0b00000000;
-
-            */
-
-            memory.data[0x0] = 0x10;
-
-            // Load update enable value into r3 (Can be any value above 0)
-            memory.data[0xF03] = (crate::OpCode::LOAD_IMM as u8) << 1;
-            memory.data[0xF02] = 0b00110000; // r3
-            memory.data[0xF01] = 0b00000000;
-            memory.data[0xF00] = 0b00000001; // 255
-
-            // Load GPU update enable register address into r2
-            memory.data[0xF07] = (crate::OpCode::LOAD_IMM as u8) << 1;
-            memory.data[0xF06] = 0b00100000; // r2
-            memory.data[0xF05] = 0b00010000; // |
-            memory.data[0xF04] = 0b00000010; // --> GPU register 2 at 0x4098
-
-            // Store update enable value to update enable register of GPU
-            memory.data[0xF0B] = (crate::OpCode::STOR_BYTE as u8) << 1;
-            memory.data[0xF0A] = 0b00100001; // store to address in r2
-            memory.data[0xF09] = 0b10000000; // value from r3
-            memory.data[0xF08] = 0b00000000;
-
-            // Load pixel color into r1
-            memory.data[0xF0F] = (crate::OpCode::LOAD_IMM as u8) << 1;
-            memory.data[0xF0E] = 0b00010000; // r1
-            memory.data[0xF0D] = 0b00000000; // |
-            memory.data[0xF0C] = 0b00000000; // --> Some color
-
-
-            // Load frame buffer pointer to r0
-            memory.data[0xF13] = (crate::OpCode::LOAD_IMM as u8) << 1;
-            memory.data[0xF12] = 0b00000000; // r0 (fb pointer)
-            memory.data[0xF11] = 0b00000000;
-            memory.data[0xF10] = 0b00000000; // 0
-
-            // Load incrementer into r4
-            memory.data[0xF17] = (crate::OpCode::LOAD_IMM as u8) << 1;
-            memory.data[0xF16] = 0b01000000; // r4 (incrementer)
-            memory.data[0xF15] = 0b00000000; //
-            memory.data[0xF14] = 0b00000001; // 1
-
-            // Load GPU frame buffer register address into r5
-            memory.data[0xF1B] = (crate::OpCode::LOAD_IMM as u8) << 1;
-            memory.data[0xF1A] = 0b01010000; // r5 (fb address)
-            memory.data[0xF19] = 0b00010000; // |
-            memory.data[0xF18] = 0b00000000; // --> GPU register 0 at 0x4096
-
-            // Store new frame buffer pointer into fb register of GPU
-            memory.data[0xF1F] = (crate::OpCode::STOR_BYTE as u8) << 1;
-            memory.data[0xF1E] = 0b01010000; // store to address in r5
-            memory.data[0xF1D] = 0b00000000; // value from r0
-            memory.data[0xF1C] = 0b00000000; //
-
-            // Load GPU pixeldata register address into r6
-            memory.data[0xF23] = (crate::OpCode::LOAD_IMM as u8) << 1;
-            memory.data[0xF22] = 0b01100000; // r6 (pixeldata address)
-            memory.data[0xF21] = 0b00010000; // |
-            memory.data[0xF20] = 0b00000001; // --> GPU register 1 at 0x4097
-
-
-            // Store pixeldata to GPU pixeldata register
-            memory.data[0xF27] = (crate::OpCode::STOR_BYTE as u8) << 1;
-            memory.data[0xF26] = 0b01100000; // store to address in r6
-            memory.data[0xF25] = 0b10000000; // value from r1
-            memory.data[0xF24] = 0b00000000; //
-
-            // Increment frame buffer pointer to then be sent to GPU
-            memory.data[0xF2B] = (crate::OpCode::ADD as u8) << 1;
-            memory.data[0xF2A] = 0b00000010; // r0 (fb pointer)
-            memory.data[0xF29] = 0b00000000;
-            memory.data[0xF28] = 0b00000000;
-
-            // Store new frame buffer pointer into fb register of GPU
-            memory.data[0xF2F] = (crate::OpCode::STOR_BYTE as u8) << 1;
-            memory.data[0xF2E] = 0b01010000; // store to address in r5
-            memory.data[0xF2D] = 0b00000000; // value from r0
-            memory.data[0xF2C] = 0b00000000; //
-
-            // Repeat from address 0x48
-            memory.data[0xF33] = (crate::OpCode::JUMP_REL as u8) << 1;
-            memory.data[0xF32] = 0b00000000;
-            memory.data[0xF31] = 0b00000000;
-            memory.data[0xF30] = 0b00001100;
-        }
+    /// Creates a VM running from `memory`, or the built-in demo in `memory_size` bytes of RAM if
+    /// there is none. Fails if `cores` is zero or exceeds `MAX_CORES`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(memory: Option<crate::memory::Memory>, memory_size: usize, mode: crate::cpu::CpuMode, arith: crate::cpu::ArithMode, clock_hz: u32, resolution: (usize, usize), seed: Option<u64>, cores: usize) -> Result<Self, crate::cpu::CoreCountError> {
+        let bus = match memory {
+            Some(memory) => crate::mmio::Bus::new(memory),
+            None => {
+                let bus = crate::mmio::Bus::new_empty(memory_size);
+                VM::load_demo(&mut bus.ram.write().unwrap());
+                bus
+            }
+        };
 
         let bus = std::sync::Arc::new(std::sync::RwLock::new(bus.clone()));
 
         let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
-        let cpu = crate::cpu::CPU::new(crate::cpu::CpuMode::Debug, bus.clone(), running.clone());
-        Self {
+        let cpu = crate::cpu::CPU::new(mode, bus.clone(), running.clone(), clock_hz, arith, cores)?;
+        let gpu = std::sync::Arc::new(std::sync::Mutex::new(crate::gpu::GPU::init(0x1000, resolution.0, resolution.1, seed, bus.read().unwrap().ram.clone())));
+        Ok(Self {
             cpu,
             bus,
-            running
+            running,
+            resolution,
+            clock_hz,
+            gpu,
+            save_state: None,
+            headless: false,
+            disk: None
+        })
+    }
+
+    /// Captures the state of all cores, RAM and the GPU registers. The cores must not be running.
+    pub fn snapshot(&self) -> crate::snapshot::VmSnapshot {
+        let bus = self.bus.read().unwrap();
+        let memory = bus.ram.read().unwrap();
+        let used = memory.used_len();
+        crate::snapshot::VmSnapshot {
+            cores: self.cpu.cores.iter().flatten().map(|core| core.state()).collect(),
+            memory_size: memory.data.len(),
+            ram: memory.data[..used].to_vec(),
+            gpu_registers: self.gpu.lock().unwrap().registers.to_vec(),
+        }
+    }
+
+    /// Writes the RAM bytes in `range` to the file at `path`.
+    pub fn dump_memory(&self, range: std::ops::Range<u32>, path: &str) -> std::io::Result<()> {
+        self.bus.read().unwrap().ram.read().unwrap().dump(range, path)
+    }
+
+    /// Restores a snapshot taken with `snapshot`. The cores must not be running. Fails without
+    /// changing anything if the snapshot was taken with a different number of cores or memory size.
+    pub fn restore(&mut self, snapshot: crate::snapshot::VmSnapshot) -> Result<(), crate::snapshot::RestoreError> {
+        let cores = self.cpu.cores.iter().flatten().count();
+        if snapshot.cores.len() != cores {
+            return Err(crate::snapshot::RestoreError::CoreCount { snapshot: snapshot.cores.len(), vm: cores });
+        }
+        let memory_size = self.bus.read().unwrap().ram.read().unwrap().data.len();
+        if snapshot.memory_size != memory_size || snapshot.ram.len() > memory_size {
+            return Err(crate::snapshot::RestoreError::MemorySize { snapshot: snapshot.memory_size, vm: memory_size });
+        }
+        for (core, state) in self.cpu.cores.iter_mut().flatten().zip(&snapshot.cores) {
+            core.restore_state(state);
+        }
+        {
+            let bus = self.bus.read().unwrap();
+            let mut memory = bus.ram.write().unwrap();
+            let used = memory.used_len();
+            if used > snapshot.ram.len() {
+                memory.data[snapshot.ram.len()..used].fill(0);
+            }
+            memory.data[..snapshot.ram.len()].copy_from_slice(&snapshot.ram);
+        }
+        let mut gpu = self.gpu.lock().unwrap();
+        for (register, value) in gpu.registers.iter_mut().zip(snapshot.gpu_registers) {
+            *register = value;
+        }
+        Ok(())
+    }
+
+    /// Writes the built-in pixel drawing demo to memory. Used when no ROM is given.
+    fn load_demo(memory: &mut crate::memory::Memory) {
+        use crate::OpCode;
+        use crate::encoding::{encode_i, encode_j, encode_r};
+
+        /*
+        memory.data[0x0] = 0x18; // Core 0 reset addr
+        memory.data[0x4] = 0x84; // Core 1 reset addr
+        memory.data[0x27] = (OpCode::IRPT_SEND as u8) << 1;
+        // xxxxxxxx xxxxxxxx xxxxxxxx xxxxxxxx
+        memory.data[0x26] = 0b00010000;
+        memory.data[0x25] = 0b10000000;
+
+        memory.data[0x87] = (OpCode::IRPT_SEND as u8) << 1;
+        memory.data[0x86] = 0b00000001;
+        memory.data[0x85] = 0b00000000;
+
+        */
+
+        // Reset vector of core 0, pointing at the first instruction
+        memory.data[0x0..0x4].copy_from_slice(&0xF00u32.to_le_bytes());
+
+        let program = [
+            // Update register value: bit 0 enables updates, draw mode 0 draws pixels
+            encode_i(OpCode::LOAD_IMM, 3, 0x1),
+            // GPU update register, stored from r3
+            encode_i(OpCode::LOAD_IMM, 2, 0x1002),
+            encode_r(OpCode::STOR_BYTE, 2, 3, 0),
+            // Pixel color in r1 (blue, stores only send the low byte), frame buffer pointer in r0,
+            // incrementer in r4
+            encode_i(OpCode::LOAD_IMM, 1, 0xFF),
+            encode_i(OpCode::LOAD_IMM, 0, 0x0),
+            encode_i(OpCode::LOAD_IMM, 4, 0x1),
+            // GPU frame buffer index register in r5, stored from r0
+            encode_i(OpCode::LOAD_IMM, 5, 0x1000),
+            encode_r(OpCode::STOR_BYTE, 5, 0, 0),
+            // GPU pixel data register in r6, stored from r1
+            encode_i(OpCode::LOAD_IMM, 6, 0x1001),
+            encode_r(OpCode::STOR_BYTE, 6, 1, 0),
+            // GPU present register in r7
+            encode_i(OpCode::LOAD_IMM, 7, 0x1004),
+            // 0xF2C: Increment the frame buffer pointer and send it to the GPU
+            encode_r(OpCode::ADD, 0, 4, 0),
+            encode_r(OpCode::STOR_BYTE, 5, 0, 0),
+            // Present the frame, r4 is non-zero
+            encode_r(OpCode::STOR_BYTE, 7, 4, 0),
+            // Repeat from 0xF2C, 16 bytes before the instruction following this one
+            encode_j(OpCode::JUMP_REL, -16i32 as u32),
+        ];
+        for (i, instruction) in program.iter().enumerate() {
+            let addr = 0xF00 + i * 4;
+            memory.data[addr..addr + 4].copy_from_slice(&instruction.to_le_bytes());
         }
     }
 
-    pub fn run(self) {
+    /// Maps the devices and runs the CPU, plus the timer and the window unless `headless` is set.
+    /// Blocks until the VM stops and returns it, so the final state of the cores and memory can be
+    /// inspected. Fails with the error that stopped the VM if the CPU mode doesn't tolerate it.
+    pub fn run(mut self) -> Result<Self, crate::cpu::CpuError> {
         let mut handles = Vec::new();
+        #[cfg(feature = "gpu")]
         let running = self.running.clone();
         info!("Starting VM in {} mode...", format!("{}", self.cpu.mode));
 
+        // Devices have to be mapped before the CPU starts so its first stores are forwarded
+        let gpu = self.gpu.clone();
+        self.bus.write().unwrap().map_device(crate::mmio::MmioRegion {
+            name: "GPU".to_string(),
+            base: 0x1000,
+            size: 0x10,
+            device: gpu.clone()
+        }).unwrap();
+        self.bus.write().unwrap().map_device(crate::mmio::MmioRegion {
+            name: "GPU Layers".to_string(),
+            base: 0x1050,
+            size: 0x10,
+            device: std::sync::Arc::new(std::sync::Mutex::new(crate::gpu::GpuLayers(gpu.clone())))
+        }).unwrap();
+        let keyboard = std::sync::Arc::new(std::sync::Mutex::new(crate::keyboard::Keyboard::new()));
+        self.bus.write().unwrap().map_device(crate::mmio::MmioRegion {
+            name: "Keyboard".to_string(),
+            base: 0x1010,
+            size: 0x10,
+            device: keyboard.clone()
+        }).unwrap();
+        let timer = std::sync::Arc::new(std::sync::Mutex::new(crate::timer::Timer::new(self.cpu.senders.clone())));
+        self.bus.write().unwrap().map_device(crate::mmio::MmioRegion {
+            name: "Timer".to_string(),
+            base: 0x1020,
+            size: 0x10,
+            device: timer.clone()
+        }).unwrap();
+        let console = std::sync::Arc::new(std::sync::Mutex::new(crate::console::Console::new(Box::new(std::io::stdout()))));
+        self.bus.write().unwrap().map_device(crate::mmio::MmioRegion {
+            name: "Console".to_string(),
+            base: 0x1030,
+            size: 0x10,
+            device: console
+        }).unwrap();
+        let memory_size = self.bus.read().unwrap().ram.read().unwrap().data.len();
+        let sysinfo = crate::sysinfo::SysInfo::new(memory_size, self.cpu.cores.len() as u32, self.clock_hz);
+        self.bus.write().unwrap().map_device(crate::mmio::MmioRegion {
+            name: "SysInfo".to_string(),
+            base: 0x1060,
+            size: 0x10,
+            device: std::sync::Arc::new(std::sync::Mutex::new(sysinfo))
+        }).unwrap();
+        let dma = crate::dma::Dma::new(self.bus.read().unwrap().ram.clone());
+        self.bus.write().unwrap().map_device(crate::mmio::MmioRegion {
+            name: "Dma".to_string(),
+            base: 0x1070,
+            size: 0x10,
+            device: std::sync::Arc::new(std::sync::Mutex::new(dma))
+        }).unwrap();
+        if let Some(file) = self.disk.take() {
+            let ram = self.bus.read().unwrap().ram.clone();
+            let disk = std::sync::Arc::new(std::sync::Mutex::new(crate::block::BlockDevice::new(file, ram)));
+            self.bus.write().unwrap().map_device(crate::mmio::MmioRegion {
+                name: "BlockDevice".to_string(),
+                base: 0x1040,
+                size: 0x10,
+                device: disk
+            }).unwrap();
+        }
+
+        #[cfg(feature = "gpu")]
+        let (width, height) = self.resolution;
+        #[cfg(feature = "gpu")]
+        let senders = self.cpu.senders.clone();
         let mut cpu = self.cpu;
+        cpu.exit_when_idle = self.headless;
         let cpu_handle = std::thread::Builder::new()
             .name("Rusty-VM-CPU".to_string())
             .spawn(move || {
                 info!("Starting CPU...");
-                cpu.run();
+                let result = cpu.run();
+                (cpu, result)
             })
             .unwrap();
-        handles.push(cpu_handle);
 
-        let gpu = std::sync::Arc::new(std::sync::Mutex::new(crate::gpu::GPU::init(0x1000)));
-        self.bus.write().unwrap().regions.push(crate::mmio::MmioRegion {
-            name: "GPU".to_string(),
-            base: 0x1000,
-            size: 0x10,
-            device: gpu.clone()
-        });
-        let gpu_handle = std::thread::Builder::new()
-            .name("Rusty-VM-GPU".to_string())
+        let timer_running = self.running.clone();
+        let timer_handle = std::thread::Builder::new()
+            .name("Rusty-VM-Timer".to_string())
             .spawn(move || {
-                info!("Starting GPU...");
-                let mut window = minifb::Window::new(
-                    "RustyVM - 2",
-                    (1280) as usize,
-                    (720) as usize,
-                    minifb::WindowOptions {
-                        resize: false,
-                        scale: minifb::Scale::X1,
-                        scale_mode: minifb::ScaleMode::Stretch,
-                        ..Default::default()
-                    }
-                ).unwrap();
-                window.set_target_fps(60);
-                window.set_cursor_visibility(false);
-                while window.is_open() && !window.is_key_down(minifb::Key::Escape) {
-                    let fb = {
-                        let gpu_guard = gpu.lock().unwrap();
-                        gpu_guard.frame_buffer.clone()
-                    };
-                    {
-                        gpu.lock().unwrap().update().unwrap();
-                    }
-                    window.update_with_buffer(fb.as_slice() , 1280, 720)
-                        .unwrap();
+                info!("Starting Timer...");
+                while timer_running.load(std::sync::atomic::Ordering::Relaxed) {
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                    timer.lock().unwrap().tick();
                 }
-                running.store(false, std::sync::atomic::Ordering::Relaxed);
-                info!("Terminating threads...")
             })
             .unwrap();
-        handles.push(gpu_handle);
+        handles.push(timer_handle);
+
+        #[cfg(feature = "gpu")]
+        if !self.headless {
+            let gpu_handle = std::thread::Builder::new()
+                .name("Rusty-VM-GPU".to_string())
+                .spawn(move || {
+                    info!("Starting GPU...");
+                    let mut window = minifb::Window::new(
+                        "RustyVM - 2",
+                        width,
+                        height,
+                        minifb::WindowOptions {
+                            resize: false,
+                            scale: minifb::Scale::X1,
+                            scale_mode: minifb::ScaleMode::Stretch,
+                            ..Default::default()
+                        }
+                    ).unwrap();
+                    window.set_target_fps(60);
+                    window.set_cursor_visibility(false);
+                    // Only rows that changed since the last frame are copied into this buffer
+                    let mut fb = vec![0u32; width * height];
+                    while window.is_open()
+                        && !window.is_key_down(minifb::Key::Escape)
+                        && running.load(std::sync::atomic::Ordering::Relaxed)
+                    {
+                        {
+                            let mut gpu_guard = gpu.lock().unwrap();
+                            gpu_guard.copy_presented(&mut fb);
+                            gpu_guard.update().unwrap();
+                        }
+                        keyboard.lock().unwrap().set_keys(&window.get_keys());
+                        window.update_with_buffer(fb.as_slice() , width, height)
+                            .unwrap();
+                        gpu.lock().unwrap().send_vsync(&senders);
+                    }
+                    running.store(false, std::sync::atomic::Ordering::Relaxed);
+                    info!("Terminating threads...")
+                })
+                .unwrap();
+            handles.push(gpu_handle);
+        }
 
         for handle in handles {
             handle.join().unwrap();
         }
+        let (cpu, result) = cpu_handle.join().unwrap();
+        self.cpu = cpu;
 
-        loop {}
+        if let Some(path) = &self.save_state
+            && let Err(e) = self.snapshot().save(path)
+        {
+            error!("Could not save VM state to {}: {}", path, e);
+        }
+        result.map(|()| self)
     }
 }
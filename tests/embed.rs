@@ -0,0 +1,115 @@
+//! Embeds the VM through the library API and inspects the cores after it stopped.
+
+use rusty_vm_2::{ArithMode, CpuMode, VM};
+
+#[test]
+fn runs_an_assembled_program_to_completion() {
+    let source = ".word start\n.org 0x100\nstart:\n    load_imm r1, 5\n    addi r1, 1\n    halt\n";
+    let image = rusty_vm_2::asm::assemble(source).unwrap();
+    let size = rusty_vm_2::vm::MIN_MEMORY_SIZE;
+    let memory = rusty_vm_2::memory::Memory::from_bytes(&image, size).unwrap();
+    let mut vm = VM::new(Some(memory), size, CpuMode::Safe, ArithMode::Checked, 0, (64, 64), Some(0), 1).unwrap();
+    vm.headless = true;
+    vm.cpu.set_max_cycles(100);
+
+    let vm = vm.run().unwrap();
+    let core = vm.cpu.cores[0].as_ref().unwrap();
+    assert_eq!(core.registers[1], 6);
+    assert!(core.halted);
+}
+
+#[test]
+fn steps_a_single_core_without_waiting_for_the_clock() {
+    let source = ".word start\n.org 0x100\nstart:\n    load_imm r1, 5\n    addi r1, 1\n    halt\n";
+    let image = rusty_vm_2::asm::assemble(source).unwrap();
+    let size = rusty_vm_2::vm::MIN_MEMORY_SIZE;
+    let memory = rusty_vm_2::memory::Memory::from_bytes(&image, size).unwrap();
+    // At 1 Hz a single instruction would take a second if the clock was honored
+    let mut vm = VM::new(Some(memory), size, CpuMode::Safe, ArithMode::Checked, 1, (64, 64), Some(0), 2).unwrap();
+
+    let start = std::time::Instant::now();
+    vm.cpu.step(0).unwrap();
+    assert_eq!(vm.cpu.cores[0].as_ref().unwrap().registers[1], 5);
+    vm.cpu.step(0).unwrap();
+    assert_eq!(vm.cpu.cores[0].as_ref().unwrap().registers[1], 6);
+    assert!(start.elapsed() < std::time::Duration::from_millis(500));
+
+    // Core 1 idles until it is resumed
+    vm.cpu.step(1).unwrap();
+    assert_eq!(vm.cpu.cores[1].as_ref().unwrap().instructions_retired, 0);
+
+    assert_eq!(vm.cpu.step(0).unwrap_err().error_type, rusty_vm_2::CpuErrorType::Halt);
+    assert_eq!(vm.cpu.step(2).unwrap_err().error_type, rusty_vm_2::CpuErrorType::CoreUnavailable(2));
+}
+
+#[test]
+fn rejects_unsupported_core_counts() {
+    let size = rusty_vm_2::vm::MIN_MEMORY_SIZE;
+    let max = rusty_vm_2::core::MAX_CORES as usize;
+    assert!(VM::new(None, size, CpuMode::Safe, ArithMode::Checked, 0, (64, 64), Some(0), 8).is_ok());
+    assert!(VM::new(None, size, CpuMode::Safe, ArithMode::Checked, 0, (64, 64), Some(0), max).is_ok());
+    assert!(VM::new(None, size, CpuMode::Safe, ArithMode::Checked, 0, (64, 64), Some(0), max + 1).is_err());
+    assert!(VM::new(None, size, CpuMode::Safe, ArithMode::Checked, 0, (64, 64), Some(0), 0).is_err());
+}
+
+#[test]
+fn default_stack_fits_into_the_smallest_memory() {
+    let source = ".word start\n.org 0x100\nstart:\n    load_imm r1, 7\n    push r1\n    pop r2\n    halt\n";
+    let image = rusty_vm_2::asm::assemble(source).unwrap();
+    let size = rusty_vm_2::vm::MIN_MEMORY_SIZE;
+    let memory = rusty_vm_2::memory::Memory::from_bytes(&image, size).unwrap();
+    let mut vm = VM::new(Some(memory), size, CpuMode::Safe, ArithMode::Checked, 0, (64, 64), Some(0), 1).unwrap();
+    vm.headless = true;
+    vm.cpu.set_max_cycles(100);
+
+    let vm = vm.run().unwrap();
+    let core = vm.cpu.cores[0].as_ref().unwrap();
+    assert_eq!(core.registers[2], 7);
+    assert!(core.halted);
+}
+
+#[test]
+fn rejects_a_stack_outside_of_memory() {
+    let size = rusty_vm_2::vm::MIN_MEMORY_SIZE;
+    let mut vm = VM::new(None, size, CpuMode::Safe, ArithMode::Checked, 0, (64, 64), Some(0), 1).unwrap();
+    assert!(vm.cpu.set_stack(size as u32 - 0x100, 0x100).is_ok());
+    assert!(vm.cpu.set_stack(size as u32 - 0x100, 0x101).is_err());
+}
+
+#[test]
+fn returns_the_error_that_stopped_the_vm() {
+    // 0x7F is not an opcode
+    let source = ".word start\n.org 0x100\nstart:\n    .word 0xFE000000\n";
+    let image = rusty_vm_2::asm::assemble(source).unwrap();
+    let size = rusty_vm_2::vm::MIN_MEMORY_SIZE;
+    let memory = rusty_vm_2::memory::Memory::from_bytes(&image, size).unwrap();
+    let mut vm = VM::new(Some(memory), size, CpuMode::Safe, ArithMode::Checked, 0, (64, 64), Some(0), 1).unwrap();
+    vm.headless = true;
+
+    let error = vm.run().err().unwrap();
+    assert_eq!(error.error_type, rusty_vm_2::CpuErrorType::InvalidOpCode(0x7F));
+}
+
+#[test]
+fn rejects_snapshots_of_a_different_vm() {
+    let size = rusty_vm_2::vm::MIN_MEMORY_SIZE;
+    let new_vm = |size, cores| VM::new(None, size, CpuMode::Safe, ArithMode::Checked, 0, (64, 64), Some(0), cores).unwrap();
+    let snapshot = new_vm(size, 2).snapshot();
+
+    assert!(new_vm(size, 1).restore(new_vm(size, 2).snapshot()).is_err());
+    assert!(new_vm(size * 2, 2).restore(new_vm(size, 2).snapshot()).is_err());
+    assert!(new_vm(size, 2).restore(snapshot).is_ok());
+}
+
+#[test]
+fn dumps_a_memory_range_to_a_file() {
+    let size = rusty_vm_2::vm::MIN_MEMORY_SIZE;
+    let vm = VM::new(None, size, CpuMode::Safe, ArithMode::Checked, 0, (64, 64), Some(0), 1).unwrap();
+    let path = std::env::temp_dir().join(format!("rusty-vm-dump-{}.bin", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    vm.dump_memory(0x0..0x4, path).unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), 0xF00u32.to_le_bytes());
+    assert!(vm.dump_memory(0x0..size as u32 + 1, path).is_err());
+    std::fs::remove_file(path).unwrap();
+}
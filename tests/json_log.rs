@@ -0,0 +1,40 @@
+//! Runs the VM binary headless on a small program and checks the JSON trace log it writes.
+
+#[test]
+fn log_file_contains_json_instruction_events() {
+    let dir = std::env::temp_dir().join(format!("rusty-vm-json-log-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let program = dir.join("program.s");
+    let log = dir.join("log.json");
+    std::fs::write(
+        &program,
+        ".word start\n.org 0x100\nstart:\n    load_imm r1, 5\n    addi r1, 1\n    halt\n",
+    )
+    .unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_rusty-vm-2"))
+        .args(["--headless", "--clock-hz", "0", "--max-cycles", "100"])
+        .arg("--asm")
+        .arg(&program)
+        .arg("--log-file")
+        .arg(&log)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let contents = std::fs::read_to_string(&log).unwrap();
+    let events: Vec<serde_json::Value> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("log line is not valid JSON"))
+        .collect();
+    let instruction = events
+        .iter()
+        .find(|event| event["fields"]["opcode"] == "ADDI")
+        .expect("no event for the ADDI instruction");
+    assert_eq!(instruction["span"]["core"], 0);
+    assert_eq!(instruction["fields"]["pc"], 0x104);
+    assert_eq!(instruction["fields"]["operands"], "r1, 0x1");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}